@@ -0,0 +1,204 @@
+use futures::stream::{Fuse, FuturesOrdered};
+use futures::task::{Context, Poll};
+use futures::{stream, Future, Stream, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() {
+    println!(
+        "Resources from first 5 pages, buffered by 3:\n{:?}",
+        collect_resources_n_pages_buffered(5, 3).await
+    );
+    println!(
+        "Resources from first 5 pages, buffer-unordered by 3:\n{:?}",
+        collect_resources_n_pages_buffer_unordered(5, 3).await
+    );
+    println!(
+        "Resources from first 5 pages, partitioned by id % 2, buffered by 3:\n{:?}",
+        collect_resources_n_pages_partitioned(5, 3, |id| id % 2).await
+    );
+    println!(
+        "Resources from first 5 pages, partitioned by page, buffered by 3:\n{:?}",
+        collect_resources_n_pages_partitioned(5, 3, |id| id / 10).await
+    );
+}
+
+async fn collect_resources_n_pages_buffered(n: usize, buf_factor: usize) -> Vec<Resource> {
+    get_ids_n_pages_buffered(n, buf_factor)
+        .map(fetch_resource)
+        .buffered(buf_factor)
+        .collect()
+        .await
+}
+
+async fn collect_resources_n_pages_buffer_unordered(n: usize, buf_factor: usize) -> Vec<Resource> {
+    get_ids_n_pages_buffer_unordered(n, buf_factor)
+        .map(fetch_resource)
+        .buffer_unordered(buf_factor)
+        .collect()
+        .await
+}
+
+async fn collect_resources_n_pages_partitioned<K: Eq + Hash + Clone + Unpin>(
+    n: usize,
+    buf_factor: usize,
+    key: impl Fn(usize) -> K,
+) -> Vec<Resource> {
+    buffered_partitioned(
+        get_ids_n_pages_buffered(n, buf_factor).map(|id| (key(id), fetch_resource(id))),
+        buf_factor,
+    )
+    .collect()
+    .await
+}
+
+fn get_ids_n_pages_buffered(n: usize, buf_factor: usize) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .buffered(buf_factor)
+        .flat_map(stream::iter)
+}
+
+fn get_ids_n_pages_buffer_unordered(n: usize, buf_factor: usize) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .buffer_unordered(buf_factor)
+        .flat_map(stream::iter)
+}
+
+fn get_pages_futures() -> impl Stream<Item = impl Future<Output = Vec<usize>>> {
+    stream::iter(0..).map(get_page)
+}
+
+fn buffered_partitioned<K, Fut, S>(stream: S, buf_factor: usize) -> BufferedPartitioned<K, Fut, S>
+where
+    K: Eq + Hash,
+    Fut: Future,
+    S: Stream<Item = (K, Fut)>,
+{
+    BufferedPartitioned {
+        stream: Box::pin(stream.fuse()),
+        partitions: HashMap::new(),
+        order: VecDeque::new(),
+        buf_factor,
+    }
+}
+
+struct BufferedPartitioned<K, Fut: Future, S> {
+    stream: Pin<Box<Fuse<S>>>,
+    partitions: HashMap<K, FuturesOrdered<Fut>>,
+    order: VecDeque<K>,
+    buf_factor: usize,
+}
+
+impl<K, Fut, S> BufferedPartitioned<K, Fut, S>
+where
+    Fut: Future,
+{
+    fn len(&self) -> usize {
+        self.partitions.values().map(|p| p.len()).sum()
+    }
+}
+
+impl<K, Fut, S> Stream for BufferedPartitioned<K, Fut, S>
+where
+    K: Eq + Hash + Clone + Unpin,
+    Fut: Future,
+    S: Stream<Item = (K, Fut)>,
+{
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while this.len() < this.buf_factor {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some((key, fut))) => {
+                    if !this.partitions.contains_key(&key) {
+                        this.order.push_back(key.clone());
+                    }
+                    this.partitions.entry(key).or_default().push_back(fut);
+                }
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+
+        let mut output = None;
+        for _ in 0..this.order.len() {
+            let key = this.order.pop_front().unwrap();
+            let partition = this.partitions.get_mut(&key).unwrap();
+            let poll = partition.poll_next_unpin(cx);
+            if partition.is_empty() {
+                this.partitions.remove(&key);
+            } else {
+                this.order.push_back(key);
+            }
+            if let Poll::Ready(Some(item)) = poll {
+                output = Some(item);
+                break;
+            }
+        }
+
+        match output {
+            Some(item) => Poll::Ready(Some(item)),
+            None if this.partitions.is_empty() && this.stream.is_done() => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+async fn get_page(i: usize) -> Vec<usize> {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] # get_page({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] # get_page({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+
+    (10 * i..10 * i + 5).collect()
+}
+
+#[derive(Clone, Copy)]
+struct Resource(usize);
+
+impl std::fmt::Debug for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("r:{}", self.0))
+    }
+}
+
+async fn fetch_resource(i: usize) -> Resource {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] ## fetch_resource({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## fetch_resource({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+    Resource(i)
+}
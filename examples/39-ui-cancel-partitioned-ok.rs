@@ -0,0 +1,352 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Fuse, FuturesOrdered, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use futures::{future, join, Future};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 25 queries, buffered by 3");
+    cancel_queries(5, 3, Strategy::Buffered).await?;
+    println!("Cancel 25 queries, buffer-unordered by 3");
+    cancel_queries(5, 3, Strategy::BufferUnordered).await?;
+    println!("Cancel 25 queries, partitioned by id % 2, buffered by 3");
+    cancel_queries(5, 3, Strategy::Partitioned(2)).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Strategy {
+    Buffered,
+    BufferUnordered,
+    Partitioned(usize),
+}
+
+async fn cancel_queries(
+    n: usize,
+    buf_factor: usize,
+    strategy: Strategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        let rx = cancel(rx, &valid_reader, &counter_writer);
+        match strategy {
+            Strategy::Buffered => {
+                receive_task_buffered(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+            Strategy::BufferUnordered => {
+                receive_task_buffer_unordered(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+            Strategy::Partitioned(k) => {
+                receive_task_partitioned(rx, buf_factor, k, &valid_reader, &counter_writer).await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(get_data)
+        .buffered(buf_factor)
+        .for_each(|data| observe(data, valid_reader, counter_writer))
+        .await;
+}
+
+async fn receive_task_buffer_unordered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(get_data)
+        .buffer_unordered(buf_factor)
+        .for_each(|data| observe(data, valid_reader, counter_writer))
+        .await;
+}
+
+async fn receive_task_partitioned(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    k: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    buffered_partitioned(rx.map(|i| (i % k, get_data(i))), buf_factor)
+        .for_each(|data| observe(data, valid_reader, counter_writer))
+        .await;
+}
+
+async fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+fn buffered_partitioned<K, Fut, S>(stream: S, buf_factor: usize) -> BufferedPartitioned<K, Fut, S>
+where
+    K: Eq + Hash,
+    Fut: Future,
+    S: Stream<Item = (K, Fut)>,
+{
+    BufferedPartitioned {
+        stream: Box::pin(stream.fuse()),
+        partitions: HashMap::new(),
+        order: VecDeque::new(),
+        buf_factor,
+    }
+}
+
+struct BufferedPartitioned<K, Fut: Future, S> {
+    stream: Pin<Box<Fuse<S>>>,
+    partitions: HashMap<K, FuturesOrdered<Fut>>,
+    order: VecDeque<K>,
+    buf_factor: usize,
+}
+
+impl<K, Fut, S> BufferedPartitioned<K, Fut, S>
+where
+    Fut: Future,
+{
+    fn len(&self) -> usize {
+        self.partitions.values().map(|p| p.len()).sum()
+    }
+}
+
+impl<K, Fut, S> Stream for BufferedPartitioned<K, Fut, S>
+where
+    K: Eq + Hash + Clone + Unpin,
+    Fut: Future,
+    S: Stream<Item = (K, Fut)>,
+{
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while this.len() < this.buf_factor {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some((key, fut))) => {
+                    if !this.partitions.contains_key(&key) {
+                        this.order.push_back(key.clone());
+                    }
+                    this.partitions.entry(key).or_default().push_back(fut);
+                }
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+
+        let mut output = None;
+        for _ in 0..this.order.len() {
+            let key = this.order.pop_front().unwrap();
+            let partition = this.partitions.get_mut(&key).unwrap();
+            let poll = partition.poll_next_unpin(cx);
+            if partition.is_empty() {
+                this.partitions.remove(&key);
+            } else {
+                this.order.push_back(key);
+            }
+            if let Poll::Ready(Some(item)) = poll {
+                output = Some(item);
+                break;
+            }
+        }
+
+        match output {
+            Some(item) => Poll::Ready(Some(item)),
+            None if this.partitions.is_empty() && this.stream.is_done() => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
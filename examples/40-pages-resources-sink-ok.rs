@@ -0,0 +1,213 @@
+use futures::{sink, stream, Future, Sink, Stream, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Collect resources from first 5 pages, buffered by 3");
+    collect_resources_n_pages_buffered(5, 3).await;
+
+    println!("Stream resources from first 5 pages to stdout");
+    write_resources_n_pages(5, ndjson_sink(io::stdout(), Duration::ZERO)).await?;
+
+    println!("Stream resources from first 5 pages, buffered by 3, to stdout");
+    write_resources_n_pages_buffered(5, 3, ndjson_sink(io::stdout(), Duration::ZERO)).await?;
+
+    println!("Stream resources from first 5 pages, buffer-unordered by 3, to stdout");
+    write_resources_n_pages_buffer_unordered(5, 3, ndjson_sink(io::stdout(), Duration::ZERO))
+        .await?;
+
+    let path = std::env::temp_dir().join("resources.ndjson");
+    println!(
+        "Stream resources from first 5 pages, buffered by 3, to {}",
+        path.display()
+    );
+    let file = BufWriter::new(File::create(&path).await?);
+    write_resources_n_pages_buffered(5, 3, ndjson_sink(file, Duration::ZERO)).await?;
+
+    println!("Stream resources from first 5 pages, buffered by 3, to a slow stdout (20 ms/item)");
+    write_resources_n_pages_buffered(5, 3, ndjson_sink(io::stdout(), Duration::from_millis(20)))
+        .await?;
+
+    Ok(())
+}
+
+async fn collect_resources_n_pages_buffered(n: usize, buf_factor: usize) {
+    let start = Instant::now();
+    let resources: Vec<Resource> = get_ids_n_pages_buffered(n, buf_factor)
+        .map(fetch_resource)
+        .buffered(buf_factor)
+        .collect()
+        .await;
+
+    println!(
+        "Collected {} resources, first visible after {} ms:\n{:?}",
+        resources.len(),
+        start.elapsed().as_millis(),
+        resources
+    );
+}
+
+async fn write_resources_n_pages(
+    n: usize,
+    sink: impl Sink<(Instant, Resource), Error = io::Error>,
+) -> io::Result<()> {
+    let start = Instant::now();
+    get_ids_n_pages(n)
+        .then(fetch_resource)
+        .map(|resource| Ok((start, resource)))
+        .forward(sink)
+        .await?;
+
+    println!(
+        "Streamed all resources in {} ms",
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+async fn write_resources_n_pages_buffered(
+    n: usize,
+    buf_factor: usize,
+    sink: impl Sink<(Instant, Resource), Error = io::Error>,
+) -> io::Result<()> {
+    let start = Instant::now();
+    get_ids_n_pages_buffered(n, buf_factor)
+        .map(fetch_resource)
+        .buffered(buf_factor)
+        .map(|resource| Ok((start, resource)))
+        .forward(sink)
+        .await?;
+
+    println!(
+        "Streamed all resources in {} ms",
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+async fn write_resources_n_pages_buffer_unordered(
+    n: usize,
+    buf_factor: usize,
+    sink: impl Sink<(Instant, Resource), Error = io::Error>,
+) -> io::Result<()> {
+    let start = Instant::now();
+    get_ids_n_pages_buffer_unordered(n, buf_factor)
+        .map(fetch_resource)
+        .buffer_unordered(buf_factor)
+        .map(|resource| Ok((start, resource)))
+        .forward(sink)
+        .await?;
+
+    println!(
+        "Streamed all resources in {} ms",
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+fn ndjson_sink<W: AsyncWrite + Unpin>(
+    writer: W,
+    write_delay: Duration,
+) -> impl Sink<(Instant, Resource), Error = io::Error> {
+    sink::unfold(
+        (writer, 0),
+        move |(mut writer, count), (start, resource): (Instant, Resource)| async move {
+            if count == 0 {
+                println!("Time to first item: {} ms", start.elapsed().as_millis());
+            }
+            println!(
+                "[{}] ### write_resource({})",
+                START_TIME.elapsed().as_millis(),
+                resource.0
+            );
+
+            writer
+                .write_all(format!("{{\"id\":{}}}\n", resource.0).as_bytes())
+                .await?;
+            writer.flush().await?;
+            sleep(write_delay).await;
+            Ok((writer, count + 1))
+        },
+    )
+}
+
+fn get_ids_n_pages(n: usize) -> impl Stream<Item = usize> {
+    get_pages().take(n).flat_map(stream::iter)
+}
+
+fn get_ids_n_pages_buffered(n: usize, buf_factor: usize) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .buffered(buf_factor)
+        .flat_map(stream::iter)
+}
+
+fn get_ids_n_pages_buffer_unordered(n: usize, buf_factor: usize) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .buffer_unordered(buf_factor)
+        .flat_map(stream::iter)
+}
+
+fn get_pages() -> impl Stream<Item = Vec<usize>> {
+    stream::iter(0..).then(get_page)
+}
+
+fn get_pages_futures() -> impl Stream<Item = impl Future<Output = Vec<usize>>> {
+    stream::iter(0..).map(get_page)
+}
+
+async fn get_page(i: usize) -> Vec<usize> {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] # get_page({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] # get_page({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+
+    (10 * i..10 * i + 5).collect()
+}
+
+#[derive(Clone, Copy)]
+struct Resource(usize);
+
+impl std::fmt::Debug for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("r:{}", self.0))
+    }
+}
+
+async fn fetch_resource(i: usize) -> Resource {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] ## fetch_resource({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## fetch_resource({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+    Resource(i)
+}
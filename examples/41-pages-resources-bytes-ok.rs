@@ -0,0 +1,240 @@
+use futures::stream::{Fuse, FuturesOrdered};
+use futures::task::{Context, Poll};
+use futures::{stream, Future, Stream, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+const KB: usize = 1024;
+
+#[tokio::main]
+async fn main() {
+    let sizes = KB..64 * KB;
+
+    println!("Resources of 1-64 KB from first 5 pages, buffered by 10");
+    let tracker = MemoryTracker::new();
+    collect_resources_n_pages_buffered(5, 10, sizes.clone(), &tracker).await;
+    tracker.print();
+
+    println!("Resources of 1-64 KB from first 5 pages, buffer-unordered by 10");
+    let tracker = MemoryTracker::new();
+    collect_resources_n_pages_buffer_unordered(5, 10, sizes.clone(), &tracker).await;
+    tracker.print();
+
+    println!("Resources of 1-64 KB from first 5 pages, buffered by 128 KB");
+    let tracker = MemoryTracker::new();
+    collect_resources_n_pages_buffered_bytes(5, 128 * KB, sizes, &tracker).await;
+    tracker.print();
+}
+
+async fn collect_resources_n_pages_buffered(
+    n: usize,
+    buf_factor: usize,
+    sizes: Range<usize>,
+    tracker: &MemoryTracker,
+) -> Vec<Resource> {
+    get_ids_n_pages_sized(n, sizes)
+        .map(|(id, size)| fetch_resource(id, size, tracker))
+        .buffered(buf_factor)
+        .map(|resource| tracker.release(resource))
+        .collect()
+        .await
+}
+
+async fn collect_resources_n_pages_buffer_unordered(
+    n: usize,
+    buf_factor: usize,
+    sizes: Range<usize>,
+    tracker: &MemoryTracker,
+) -> Vec<Resource> {
+    get_ids_n_pages_sized(n, sizes)
+        .map(|(id, size)| fetch_resource(id, size, tracker))
+        .buffer_unordered(buf_factor)
+        .map(|resource| tracker.release(resource))
+        .collect()
+        .await
+}
+
+async fn collect_resources_n_pages_buffered_bytes(
+    n: usize,
+    max_bytes: usize,
+    sizes: Range<usize>,
+    tracker: &MemoryTracker,
+) -> Vec<Resource> {
+    buffered_bytes(
+        get_ids_n_pages_sized(n, sizes).map(|(id, size)| (size, fetch_resource(id, size, tracker))),
+        max_bytes,
+    )
+    .map(|resource| tracker.release(resource))
+    .collect()
+    .await
+}
+
+fn get_ids_n_pages_sized(n: usize, sizes: Range<usize>) -> impl Stream<Item = (usize, usize)> {
+    let sizes = Uniform::from(sizes);
+    stream::iter(0..)
+        .then(get_page)
+        .take(n)
+        .flat_map(stream::iter)
+        .map(move |id| (id, sizes.sample(&mut rand::thread_rng())))
+}
+
+fn buffered_bytes<Fut, S>(stream: S, max_bytes: usize) -> BufferedBytes<Fut, S>
+where
+    Fut: Future,
+    S: Stream<Item = (usize, Fut)>,
+{
+    BufferedBytes {
+        stream: Box::pin(stream.fuse()),
+        pending: None,
+        queue: FuturesOrdered::new(),
+        sizes: VecDeque::new(),
+        bytes: 0,
+        max_bytes,
+    }
+}
+
+struct BufferedBytes<Fut: Future, S> {
+    stream: Pin<Box<Fuse<S>>>,
+    pending: Option<(usize, Pin<Box<Fut>>)>,
+    queue: FuturesOrdered<Pin<Box<Fut>>>,
+    sizes: VecDeque<usize>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl<Fut, S> Stream for BufferedBytes<Fut, S>
+where
+    Fut: Future,
+    S: Stream<Item = (usize, Fut)>,
+{
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            if this.pending.is_none() {
+                match this.stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some((size, fut))) => this.pending = Some((size, Box::pin(fut))),
+                    Poll::Ready(None) | Poll::Pending => break,
+                }
+            }
+
+            let size = this.pending.as_ref().unwrap().0;
+            if !this.queue.is_empty() && this.bytes + size > this.max_bytes {
+                break;
+            }
+            let (size, fut) = this.pending.take().unwrap();
+            this.bytes += size;
+            this.sizes.push_back(size);
+            this.queue.push_back(fut);
+        }
+
+        match this.queue.poll_next_unpin(cx) {
+            Poll::Ready(Some(item)) => {
+                this.bytes -= this.sizes.pop_front().unwrap();
+                Poll::Ready(Some(item))
+            }
+            Poll::Ready(None) if this.pending.is_none() && this.stream.is_done() => {
+                Poll::Ready(None)
+            }
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+struct MemoryTracker {
+    held: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MemoryTracker {
+    fn new() -> MemoryTracker {
+        MemoryTracker {
+            held: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    fn hold(&self, bytes: usize) {
+        let held = self.held.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.peak.fetch_max(held, Ordering::SeqCst);
+    }
+
+    fn release(&self, resource: Resource) -> Resource {
+        self.held
+            .fetch_sub(resource.payload.len(), Ordering::SeqCst);
+        resource
+    }
+
+    fn print(&self) {
+        println!(
+            "Peak memory held by the reorder buffer: {} KB",
+            self.peak.load(Ordering::SeqCst) / KB
+        );
+    }
+}
+
+async fn get_page(i: usize) -> Vec<usize> {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] # get_page({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] # get_page({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+
+    (10 * i..10 * i + 5).collect()
+}
+
+#[derive(Clone)]
+struct Resource {
+    id: usize,
+    payload: Vec<u8>,
+}
+
+impl std::fmt::Debug for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("r:{}", self.id))
+    }
+}
+
+async fn fetch_resource(i: usize, size: usize, tracker: &MemoryTracker) -> Resource {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] ## fetch_resource({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## fetch_resource({}) completed with {} bytes",
+        START_TIME.elapsed().as_millis(),
+        i,
+        size
+    );
+    tracker.hold(size);
+    Resource {
+        id: i,
+        payload: vec![0; size],
+    }
+}
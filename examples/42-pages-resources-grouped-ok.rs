@@ -0,0 +1,226 @@
+use futures::{stream, Future, Stream, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() {
+    println!(
+        "Resources from first 5 pages, buffer-unordered by 3:\n{:?}",
+        collect_resources_n_pages_buffer_unordered(5, 3).await
+    );
+    println!(
+        "Resources grouped by page from first 5 pages, buffer-unordered by 3, in completion order:\n{:?}",
+        collect_grouped_resources_n_pages(5, 3, GroupOrder::Completion).await
+    );
+    println!(
+        "Resources grouped by page from first 5 pages, buffer-unordered by 3, in page order:\n{:?}",
+        collect_grouped_resources_n_pages(5, 3, GroupOrder::Page).await
+    );
+}
+
+#[derive(Clone, Copy)]
+enum GroupOrder {
+    Completion,
+    Page,
+}
+
+async fn collect_resources_n_pages_buffer_unordered(n: usize, buf_factor: usize) -> Vec<Resource> {
+    get_ids_n_pages_buffer_unordered(n, buf_factor)
+        .map(fetch_resource)
+        .buffer_unordered(buf_factor)
+        .collect()
+        .await
+}
+
+async fn collect_grouped_resources_n_pages(
+    n: usize,
+    buf_factor: usize,
+    order: GroupOrder,
+) -> Vec<(usize, Vec<Resource>)> {
+    let start = Instant::now();
+    grouped_by_page(get_pages_futures().take(n), buf_factor, order)
+        .inspect(|(page, _)| {
+            println!(
+                "[{}] ### page {} complete after {} ms",
+                START_TIME.elapsed().as_millis(),
+                page,
+                start.elapsed().as_millis()
+            )
+        })
+        .collect()
+        .await
+}
+
+fn grouped_by_page<'a>(
+    pages: impl Stream<Item = impl Future<Output = (usize, Vec<usize>)> + 'a> + 'a,
+    buf_factor: usize,
+    order: GroupOrder,
+) -> impl Stream<Item = (usize, Vec<Resource>)> + 'a {
+    let resources = pages
+        .buffer_unordered(buf_factor)
+        .flat_map(|(page, ids)| {
+            let len = ids.len();
+            let slots: Vec<Slot> = if len == 0 {
+                vec![Slot {
+                    page,
+                    index: 0,
+                    len,
+                    id: None,
+                }]
+            } else {
+                ids.into_iter()
+                    .enumerate()
+                    .map(|(index, id)| Slot {
+                        page,
+                        index,
+                        len,
+                        id: Some(id),
+                    })
+                    .collect()
+            };
+            stream::iter(slots)
+        })
+        .map(|slot| async move {
+            match slot.id {
+                Some(id) => (slot, Some(fetch_resource(id).await)),
+                None => (slot, None),
+            }
+        })
+        .buffer_unordered(buf_factor);
+
+    let mut groups = Groups::new(order);
+    resources
+        .map(Some)
+        .chain(stream::once(async { None }))
+        .flat_map(move |item| {
+            stream::iter(match item {
+                Some((slot, resource)) => groups.insert(slot, resource),
+                None => groups.flush(),
+            })
+        })
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    page: usize,
+    index: usize,
+    len: usize,
+    id: Option<usize>,
+}
+
+struct Groups {
+    order: GroupOrder,
+    partial: HashMap<usize, Vec<Option<Resource>>>,
+    complete: BTreeMap<usize, Vec<Resource>>,
+    next_page: usize,
+}
+
+impl Groups {
+    fn new(order: GroupOrder) -> Groups {
+        Groups {
+            order,
+            partial: HashMap::new(),
+            complete: BTreeMap::new(),
+            next_page: 0,
+        }
+    }
+
+    fn insert(&mut self, slot: Slot, resource: Option<Resource>) -> Vec<(usize, Vec<Resource>)> {
+        let group = self
+            .partial
+            .entry(slot.page)
+            .or_insert_with(|| vec![None; slot.len]);
+        if let Some(resource) = resource {
+            group[slot.index] = Some(resource);
+        }
+        if group.iter().any(Option::is_none) {
+            return Vec::new();
+        }
+
+        let group = self.partial.remove(&slot.page).unwrap();
+        let group = group.into_iter().map(Option::unwrap).collect();
+        match self.order {
+            GroupOrder::Completion => vec![(slot.page, group)],
+            GroupOrder::Page => {
+                self.complete.insert(slot.page, group);
+                let mut ready = Vec::new();
+                while let Some(group) = self.complete.remove(&self.next_page) {
+                    ready.push((self.next_page, group));
+                    self.next_page += 1;
+                }
+                ready
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Vec<(usize, Vec<Resource>)> {
+        std::mem::take(&mut self.complete).into_iter().collect()
+    }
+}
+
+fn get_ids_n_pages_buffer_unordered(n: usize, buf_factor: usize) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .buffer_unordered(buf_factor)
+        .flat_map(|(_, page)| stream::iter(page))
+}
+
+fn get_pages_futures() -> impl Stream<Item = impl Future<Output = (usize, Vec<usize>)>> {
+    stream::iter(0..).map(|i| async move { (i, get_page(i).await) })
+}
+
+async fn get_page(i: usize) -> Vec<usize> {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] # get_page({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] # get_page({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+
+    if i == 2 {
+        return Vec::new();
+    }
+    (10 * i..10 * i + 5).collect()
+}
+
+#[derive(Clone, Copy)]
+struct Resource(usize);
+
+impl std::fmt::Debug for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("r:{}", self.0))
+    }
+}
+
+async fn fetch_resource(i: usize) -> Resource {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] ## fetch_resource({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## fetch_resource({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+    Resource(i)
+}
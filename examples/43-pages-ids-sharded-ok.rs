@@ -0,0 +1,126 @@
+use futures::channel::mpsc::channel;
+use futures::stream::Peekable;
+use futures::{future, stream, Future, Stream, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() {
+    println!("IDs from first 20 pages, one sequential cursor");
+    report(get_ids_n_pages(20)).await;
+    println!("IDs from first 20 pages, buffered by 2");
+    report(get_ids_n_pages_buffered(20, 2)).await;
+    println!("IDs from first 20 pages, 4 shards buffered by 2");
+    report(get_ids_n_pages_sharded(20, 2, 4)).await;
+}
+
+async fn report(ids: impl Stream<Item = usize>) {
+    let start = Instant::now();
+    let ids: Vec<usize> = ids.collect().await;
+    let in_order = ids.windows(2).all(|w| w[0] < w[1]);
+    println!(
+        "Got {} ids in {} ms ({}):\n{:?}",
+        ids.len(),
+        start.elapsed().as_millis(),
+        if in_order { "in order" } else { "out of order" },
+        ids
+    );
+}
+
+fn get_ids_n_pages(n: usize) -> impl Stream<Item = usize> {
+    get_pages().take(n).flat_map(stream::iter)
+}
+
+fn get_ids_n_pages_buffered(n: usize, buf_factor: usize) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .buffered(buf_factor)
+        .flat_map(stream::iter)
+}
+
+fn get_ids_n_pages_sharded(n: usize, buf_factor: usize, k: usize) -> impl Stream<Item = usize> {
+    let shards = (0..k)
+        .map(|shard| {
+            let (tx, rx) = channel(buf_factor * 5);
+            spawn(
+                get_ids_shard_buffered(n, buf_factor, shard, k)
+                    .map(Ok)
+                    .forward(tx),
+            );
+            rx
+        })
+        .collect();
+    merge_ordered(shards)
+}
+
+fn get_ids_shard_buffered(
+    n: usize,
+    buf_factor: usize,
+    shard: usize,
+    k: usize,
+) -> impl Stream<Item = usize> {
+    get_pages_futures()
+        .take(n)
+        .enumerate()
+        .filter_map(move |(i, page)| future::ready(if i % k == shard { Some(page) } else { None }))
+        .buffered(buf_factor)
+        .flat_map(stream::iter)
+}
+
+fn merge_ordered<S: Stream<Item = usize>>(streams: Vec<S>) -> impl Stream<Item = usize> {
+    let streams: Vec<Pin<Box<Peekable<S>>>> = streams
+        .into_iter()
+        .map(|s| Box::pin(s.peekable()))
+        .collect();
+
+    stream::unfold(streams, |mut streams| async move {
+        let heads = future::join_all(
+            streams
+                .iter_mut()
+                .map(|s| async move { s.as_mut().peek().await.copied() }),
+        )
+        .await;
+        let (index, _) = heads
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, head)| head.map(|id| (index, id)))
+            .min_by_key(|(_, id)| *id)?;
+        let id = streams[index].next().await?;
+        Some((id, streams))
+    })
+}
+
+fn get_pages() -> impl Stream<Item = Vec<usize>> {
+    stream::iter(0..).then(get_page)
+}
+
+fn get_pages_futures() -> impl Stream<Item = impl Future<Output = Vec<usize>>> {
+    stream::iter(0..).map(get_page)
+}
+
+async fn get_page(i: usize) -> Vec<usize> {
+    let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+    println!(
+        "[{}] # get_page({}) will complete in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] # get_page({}) completed",
+        START_TIME.elapsed().as_millis(),
+        i
+    );
+
+    (10 * i..10 * i + 5).collect()
+}
@@ -0,0 +1,390 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{self, Fuse, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use futures::{future, join, Future};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::time::{sleep, sleep_until, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 25 queries, buffered by 3");
+    cancel_queries(5, 3, Strategy::Buffered).await?;
+    println!("Cancel 25 queries and in-flight requests, buffered by 3");
+    cancel_queries(5, 3, Strategy::BufferedCancellable).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Strategy {
+    Buffered,
+    BufferedCancellable,
+}
+
+async fn cancel_queries(
+    n: usize,
+    buf_factor: usize,
+    strategy: Strategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        let rx = cancel(rx, &valid_reader, &counter_writer);
+        match strategy {
+            Strategy::Buffered => {
+                receive_task_buffered(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+            Strategy::BufferedCancellable => {
+                receive_task_buffered_cancellable(rx, buf_factor, &valid_reader, &counter_writer)
+                    .await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(|i| get_data(i, counter_writer))
+        .buffered(buf_factor)
+        .for_each(|data| observe(data, valid_reader, counter_writer))
+        .await;
+}
+
+async fn receive_task_buffered_cancellable(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    buffered_cancellable(
+        rx.map(|i| (i, get_data(i, counter_writer))),
+        buf_factor,
+        valid_reader.clone(),
+    )
+    .for_each(|data| observe(data, valid_reader, counter_writer))
+    .await;
+}
+
+async fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+fn buffered_cancellable<Fut, S>(
+    stream: S,
+    buf_factor: usize,
+    valid_range: ValidRange,
+) -> BufferedCancellable<Fut, S>
+where
+    Fut: Future,
+    S: Stream<Item = (usize, Fut)>,
+{
+    BufferedCancellable {
+        stream: Box::pin(stream.fuse()),
+        changes: Box::pin(valid_range.changes()),
+        queue: VecDeque::new(),
+        buf_factor,
+        valid_range,
+    }
+}
+
+struct BufferedCancellable<Fut: Future, S> {
+    stream: Pin<Box<Fuse<S>>>,
+    changes: Pin<Box<dyn Stream<Item = ()> + Send>>,
+    queue: VecDeque<(usize, Slot<Fut>)>,
+    buf_factor: usize,
+    valid_range: ValidRange,
+}
+
+enum Slot<Fut: Future> {
+    Running(Pin<Box<Fut>>),
+    Done(Fut::Output),
+}
+
+impl<Fut, S> Stream for BufferedCancellable<Fut, S>
+where
+    Fut: Future,
+    Fut::Output: Unpin,
+    S: Stream<Item = (usize, Fut)>,
+{
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(())) = this.changes.as_mut().poll_next(cx) {
+            println!("## range changed");
+        }
+
+        let valid_range = &this.valid_range;
+        this.queue.retain(|(i, slot)| match slot {
+            Slot::Running(_) if !valid_range.is_valid(*i) => {
+                println!("## cancel({})", i);
+                false
+            }
+            _ => true,
+        });
+
+        while this.queue.len() < this.buf_factor {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some((i, fut))) => {
+                    this.queue.push_back((i, Slot::Running(Box::pin(fut))))
+                }
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+
+        for (_, slot) in this.queue.iter_mut() {
+            if let Slot::Running(fut) = slot {
+                if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+                    *slot = Slot::Done(output);
+                }
+            }
+        }
+
+        match this.queue.front() {
+            Some((_, Slot::Done(_))) => match this.queue.pop_front() {
+                Some((_, Slot::Done(output))) => Poll::Ready(Some(output)),
+                _ => unreachable!(),
+            },
+            None if this.stream.is_done() => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+    changed: Arc<watch::Sender<()>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let (changed, _) = watch::channel(());
+        let writer = ValidRange {
+            range: Arc::new(RwLock::new(0..0)),
+            changed: Arc::new(changed),
+        };
+        let reader = writer.clone();
+        (writer, reader)
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+        self.changed.send_replace(());
+    }
+
+    fn changes(&self) -> impl Stream<Item = ()> + Send {
+        stream::unfold(self.changed.subscribe(), |mut rx| async move {
+            rx.changed().await.ok()?;
+            Some(((), rx))
+        })
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    saved_micros: AtomicU64,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            saved_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn cancel(&self, remaining: Duration) {
+        self.record(Outcome::Cancelled);
+        self.saved_micros
+            .fetch_add(remaining.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] =
+            [0, 1, 2, 3, 4].map(|i| self.outcomes[i].load(Ordering::SeqCst));
+        let saved_micros = self.saved_micros.load(Ordering::SeqCst);
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        println!(
+            "Cancelling in flight saved {} ms of backend time",
+            saved_micros / 1000
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize, counter_writer: &ValidCounter) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    let deadline = Instant::now() + Duration::from_millis(millis);
+    let guard = CancelGuard {
+        i,
+        deadline,
+        counter_writer,
+    };
+    sleep_until(deadline).await;
+    std::mem::forget(guard);
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
+
+struct CancelGuard<'a> {
+    i: usize,
+    deadline: Instant,
+    counter_writer: &'a ValidCounter,
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        println!(
+            "[{}] ## get_data({}) cancelled with {} ms remaining",
+            START_TIME.elapsed().as_millis(),
+            self.i,
+            remaining.as_millis()
+        );
+        self.counter_writer.cancel(remaining);
+    }
+}
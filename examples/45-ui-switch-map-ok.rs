@@ -0,0 +1,373 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{self, Fuse, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use futures::{future, join, Future};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, sleep_until, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 25 queries, buffered by 3");
+    cancel_queries(5, 3, Strategy::Filter).await?;
+    println!("Switch to the latest of 5 batches of queries, buffered by 3");
+    cancel_queries(5, 3, Strategy::SwitchMap).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Strategy {
+    Filter,
+    SwitchMap,
+}
+
+async fn cancel_queries(
+    n: usize,
+    buf_factor: usize,
+    strategy: Strategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        match strategy {
+            Strategy::Filter => {
+                let rx = cancel(rx.flat_map(stream::iter), &valid_reader, &counter_writer);
+                receive_task_buffered(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+            Strategy::SwitchMap => {
+                receive_task_switch_map(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<Range<usize>>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        println!("## unbounded_send({:?})", range);
+        for _ in range.clone() {
+            counter_writer.sent();
+        }
+        tx.unbounded_send(range).unwrap();
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(|i| get_data(i, counter_writer))
+        .buffered(buf_factor)
+        .for_each(|data| observe(data, valid_reader, counter_writer))
+        .await;
+}
+
+async fn receive_task_switch_map(
+    rx: impl Stream<Item = Range<usize>>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    switch_map(rx, |range| {
+        let mut not_started = NotStarted {
+            remaining: range.len(),
+            counter_writer,
+        };
+        stream::iter(range)
+            .inspect(move |_| not_started.start())
+            .map(move |i| get_data(i, counter_writer))
+            .buffered(buf_factor)
+    })
+    .for_each(|data| observe(data, valid_reader, counter_writer))
+    .await;
+}
+
+async fn observe(
+    (result, guard): (Result<Data, QueryError>, CancelGuard<'_>),
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    std::mem::forget(guard);
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+fn switch_map<S, U, F>(stream: S, f: F) -> SwitchMap<S, U, F>
+where
+    S: Stream,
+    U: Stream,
+    F: FnMut(S::Item) -> U,
+{
+    SwitchMap {
+        stream: Box::pin(stream.fuse()),
+        inner: None,
+        f,
+    }
+}
+
+struct SwitchMap<S, U, F> {
+    stream: Pin<Box<Fuse<S>>>,
+    inner: Option<Pin<Box<U>>>,
+    f: F,
+}
+
+impl<S, U, F> Stream for SwitchMap<S, U, F>
+where
+    S: Stream,
+    U: Stream,
+    F: FnMut(S::Item) -> U + Unpin,
+{
+    type Item = U::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(item)) = this.stream.as_mut().poll_next(cx) {
+            if this.inner.is_some() {
+                println!("## switch_map: dropping previous batch");
+            }
+            this.inner = Some(Box::pin((this.f)(item)));
+        }
+
+        if let Some(inner) = this.inner.as_mut() {
+            match inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => return Poll::Ready(Some(item)),
+                Poll::Ready(None) => this.inner = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        if this.stream.is_done() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct NotStarted<'a> {
+    remaining: usize,
+    counter_writer: &'a ValidCounter,
+}
+
+impl NotStarted<'_> {
+    fn start(&mut self) {
+        self.remaining -= 1;
+    }
+}
+
+impl Drop for NotStarted<'_> {
+    fn drop(&mut self) {
+        for _ in 0..self.remaining {
+            self.counter_writer.record(Outcome::Filtered);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    saved_micros: AtomicU64,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            saved_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn cancel(&self, remaining: Duration) {
+        self.record(Outcome::Cancelled);
+        self.saved_micros
+            .fetch_add(remaining.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] =
+            [0, 1, 2, 3, 4].map(|i| self.outcomes[i].load(Ordering::SeqCst));
+        let saved_micros = self.saved_micros.load(Ordering::SeqCst);
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        println!(
+            "Cancelling in flight saved {} ms of backend time",
+            saved_micros / 1000
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+fn get_data(
+    i: usize,
+    counter_writer: &ValidCounter,
+) -> impl Future<Output = (Result<Data, QueryError>, CancelGuard<'_>)> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    let deadline = Instant::now() + Duration::from_millis(millis);
+    let guard = CancelGuard {
+        i,
+        deadline,
+        counter_writer,
+    };
+    async move {
+        sleep_until(deadline).await;
+        println!(
+            "[{}] ## get_data({}) {}",
+            START_TIME.elapsed().as_millis(),
+            i,
+            if fails { "failed" } else { "completed" }
+        );
+        if fails {
+            (Err(QueryError::Failed), guard)
+        } else {
+            (Ok(Data(i)), guard)
+        }
+    }
+}
+
+struct CancelGuard<'a> {
+    i: usize,
+    deadline: Instant,
+    counter_writer: &'a ValidCounter,
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        println!(
+            "[{}] ## get_data({}) cancelled with {} ms remaining",
+            START_TIME.elapsed().as_millis(),
+            self.i,
+            remaining.as_millis()
+        );
+        self.counter_writer.cancel(remaining);
+    }
+}
@@ -0,0 +1,389 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{self, Fuse, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use futures::{future, join, Future};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant, Sleep};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Send 4 bursts of 5 queries, buffered by 3");
+    bursty_queries(4, 5, 3, Adapter::None).await?;
+    println!("Send 4 bursts of 5 queries, debounced by 5 ms, buffered by 3");
+    bursty_queries(4, 5, 3, Adapter::Debounce(Duration::from_millis(5))).await?;
+    println!("Send 4 bursts of 5 queries, throttled by 5 ms, buffered by 3");
+    bursty_queries(4, 5, 3, Adapter::Throttle(Duration::from_millis(5))).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Adapter {
+    None,
+    Debounce(Duration),
+    Throttle(Duration),
+}
+
+async fn bursty_queries(
+    bursts: usize,
+    burst_len: usize,
+    buf_factor: usize,
+    adapter: Adapter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_bursty(tx, bursts, burst_len, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        match adapter {
+            Adapter::None => {
+                receive_task_buffered(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+            Adapter::Debounce(duration) => {
+                receive_task_buffered(
+                    debounce(rx, duration),
+                    buf_factor,
+                    &valid_reader,
+                    &counter_writer,
+                )
+                .await
+            }
+            Adapter::Throttle(duration) => {
+                receive_task_buffered(
+                    throttle(rx, duration),
+                    buf_factor,
+                    &valid_reader,
+                    &counter_writer,
+                )
+                .await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+async fn send_task_bursty(
+    tx: UnboundedSender<(Range<usize>, Instant)>,
+    bursts: usize,
+    burst_len: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..bursts * burst_len {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        println!("## unbounded_send({:?})", range);
+        for _ in range.clone() {
+            counter_writer.sent();
+        }
+        tx.unbounded_send((range, Instant::now())).unwrap();
+
+        let millis = if (i + 1) % burst_len == 0 {
+            Uniform::from(20..30).sample(&mut rand::thread_rng())
+        } else {
+            Uniform::from(0..3).sample(&mut rand::thread_rng())
+        };
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = (Range<usize>, Instant)>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.inspect(|(range, _)| counter_writer.passed(range.len()))
+        .flat_map(|(range, sent_at)| stream::iter(range.map(move |i| (i, sent_at))))
+        .filter(|(i, _)| {
+            let is_valid = valid_reader.is_valid(*i);
+            println!("## filter({}) = {}", i, is_valid);
+            if !is_valid {
+                counter_writer.record(Outcome::Filtered);
+            }
+            future::ready(is_valid)
+        })
+        .map(|(i, sent_at)| async move { (get_data(i).await, sent_at) })
+        .buffered(buf_factor)
+        .for_each(|(result, sent_at)| async move {
+            let outcome = match result {
+                Ok(data) if valid_reader.is_valid(data.0) => {
+                    counter_writer.latency(sent_at.elapsed());
+                    Outcome::Valid
+                }
+                Ok(_) => Outcome::Expired,
+                Err(QueryError::Failed) => Outcome::Failed,
+            };
+            counter_writer.record(outcome);
+            println!("## result = {:?} ({:?})", result, outcome);
+        })
+        .await;
+}
+
+fn debounce<S: Stream>(stream: S, duration: Duration) -> Debounce<S> {
+    Debounce {
+        stream: Box::pin(stream.fuse()),
+        sleep: Box::pin(sleep(duration)),
+        pending: None,
+        duration,
+    }
+}
+
+struct Debounce<S: Stream> {
+    stream: Pin<Box<Fuse<S>>>,
+    sleep: Pin<Box<Sleep>>,
+    pending: Option<S::Item>,
+    duration: Duration,
+}
+
+impl<S> Stream for Debounce<S>
+where
+    S: Stream,
+    S::Item: Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(item)) = this.stream.as_mut().poll_next(cx) {
+            if this.pending.is_some() {
+                println!("## debounce: dropping previous query");
+            }
+            this.pending = Some(item);
+            this.sleep.as_mut().reset(Instant::now() + this.duration);
+        }
+
+        if this.pending.is_some()
+            && (this.stream.is_done() || this.sleep.as_mut().poll(cx).is_ready())
+        {
+            return Poll::Ready(this.pending.take());
+        }
+        if this.stream.is_done() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+fn throttle<S: Stream>(stream: S, duration: Duration) -> Throttle<S> {
+    Throttle {
+        stream: Box::pin(stream.fuse()),
+        sleep: Box::pin(sleep(duration)),
+        pending: None,
+        cooling_down: false,
+        duration,
+    }
+}
+
+struct Throttle<S: Stream> {
+    stream: Pin<Box<Fuse<S>>>,
+    sleep: Pin<Box<Sleep>>,
+    pending: Option<S::Item>,
+    cooling_down: bool,
+    duration: Duration,
+}
+
+impl<S> Stream for Throttle<S>
+where
+    S: Stream,
+    S::Item: Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(item)) = this.stream.as_mut().poll_next(cx) {
+            if !this.cooling_down {
+                this.cooling_down = true;
+                this.sleep.as_mut().reset(Instant::now() + this.duration);
+                return Poll::Ready(Some(item));
+            }
+            if this.pending.is_some() {
+                println!("## throttle: dropping previous query");
+            }
+            this.pending = Some(item);
+        }
+
+        if this.cooling_down && this.sleep.as_mut().poll(cx).is_ready() {
+            match this.pending.take() {
+                Some(item) => {
+                    this.sleep.as_mut().reset(Instant::now() + this.duration);
+                    return Poll::Ready(Some(item));
+                }
+                None => this.cooling_down = false,
+            }
+        }
+
+        if this.stream.is_done() && this.pending.is_none() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    passed: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    latency_micros: AtomicU64,
+    max_latency_micros: AtomicU64,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            passed: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            latency_micros: AtomicU64::new(0),
+            max_latency_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn passed(&self, n: usize) {
+        self.passed.fetch_add(n, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn latency(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.latency_micros.fetch_add(micros, Ordering::SeqCst);
+        self.max_latency_micros.fetch_max(micros, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let passed = self.passed.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+        let filtered = filtered + sent - passed;
+        let latency_micros = self.latency_micros.load(Ordering::SeqCst);
+        let max_latency_micros = self.max_latency_micros.load(Ordering::SeqCst);
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+        println!(
+            "Latency of valid results: {} ms on average, {} ms at most",
+            latency_micros / valid.max(1) as u64 / 1000,
+            max_latency_micros / 1000
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,355 @@
+use futures::join;
+use futures::stream::{self, Stream, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::Notify;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Don't cancel 25 queries, unbounded queue, buffered by 3");
+    congested_queries_buffered(5, 3, usize::MAX, Overflow::Block).await?;
+    println!("Don't cancel 25 queries, queue of 5 blocking the sender, buffered by 3");
+    congested_queries_buffered(5, 3, 5, Overflow::Block).await?;
+    println!("Don't cancel 25 queries, queue of 5 dropping the oldest query, buffered by 3");
+    congested_queries_buffered(5, 3, 5, Overflow::DropOldest).await?;
+    println!("Don't cancel 25 queries, queue of 5 dropping the newest query, buffered by 3");
+    congested_queries_buffered(5, 3, 5, Overflow::DropNewest).await?;
+    Ok(())
+}
+
+async fn congested_queries_buffered(
+    n: usize,
+    buf_factor: usize,
+    capacity: usize,
+    overflow: Overflow,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = query_channel(capacity, overflow);
+    let stats = tx.clone_stats();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_observing(rx, buf_factor, &valid_reader, &counter_writer).await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    stats.print();
+    Ok(())
+}
+
+async fn send_task_tracking_validity(
+    tx: QuerySender,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## send({})", j);
+            counter_writer.sent();
+            if tx.send(j).await {
+                counter_writer.record(Outcome::Filtered);
+            }
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_observing(
+    rx: QueryReceiver,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.into_stream()
+        .map(get_data)
+        .buffered(buf_factor)
+        .for_each(|result| async move {
+            let outcome = match result {
+                Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+                Ok(_) => Outcome::Expired,
+                Err(QueryError::Failed) => Outcome::Failed,
+            };
+            counter_writer.record(outcome);
+            println!("## result = {:?} ({:?})", result, outcome);
+        })
+        .await;
+}
+
+#[derive(Clone, Copy)]
+enum Overflow {
+    Block,
+    DropOldest,
+    DropNewest,
+}
+
+fn query_channel(capacity: usize, overflow: Overflow) -> (QuerySender, QueryReceiver) {
+    assert!(capacity > 0, "query_channel requires a positive capacity");
+    let channel = Arc::new(QueryChannel {
+        state: Mutex::new(QueueState {
+            queue: VecDeque::new(),
+            closed: false,
+            dropped: 0,
+            max_depth: 0,
+        }),
+        capacity,
+        overflow,
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+    (QuerySender(channel.clone()), QueryReceiver(channel))
+}
+
+struct QueryChannel {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    overflow: Overflow,
+    readable: Notify,
+    writable: Notify,
+}
+
+struct QueueState {
+    queue: VecDeque<usize>,
+    closed: bool,
+    dropped: usize,
+    max_depth: usize,
+}
+
+impl QueueState {
+    fn log_depth(&mut self) {
+        let depth = self.queue.len();
+        self.max_depth = self.max_depth.max(depth);
+        println!(
+            "[{}] ## queue depth = {}",
+            START_TIME.elapsed().as_millis(),
+            depth
+        );
+    }
+}
+
+struct QuerySender(Arc<QueryChannel>);
+
+impl QuerySender {
+    async fn send(&self, i: usize) -> bool {
+        loop {
+            {
+                let mut state = self.0.state.lock().unwrap();
+                if state.queue.len() < self.0.capacity {
+                    state.queue.push_back(i);
+                    state.log_depth();
+                    self.0.readable.notify_one();
+                    return false;
+                }
+                match self.0.overflow {
+                    Overflow::Block => println!("## send({}) blocked", i),
+                    Overflow::DropOldest => {
+                        let oldest = state.queue.pop_front().unwrap();
+                        println!("## send({}) dropped query {}", i, oldest);
+                        state.dropped += 1;
+                        state.queue.push_back(i);
+                        state.log_depth();
+                        self.0.readable.notify_one();
+                        return true;
+                    }
+                    Overflow::DropNewest => {
+                        println!("## send({}) dropped query {}", i, i);
+                        state.dropped += 1;
+                        return true;
+                    }
+                }
+            }
+            self.0.writable.notified().await;
+        }
+    }
+
+    fn clone_stats(&self) -> QueryStats {
+        QueryStats(self.0.clone())
+    }
+}
+
+impl Drop for QuerySender {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().closed = true;
+        self.0.readable.notify_one();
+    }
+}
+
+struct QueryReceiver(Arc<QueryChannel>);
+
+impl QueryReceiver {
+    async fn recv(&self) -> Option<usize> {
+        loop {
+            {
+                let mut state = self.0.state.lock().unwrap();
+                if let Some(i) = state.queue.pop_front() {
+                    state.log_depth();
+                    self.0.writable.notify_one();
+                    return Some(i);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.0.readable.notified().await;
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = usize> {
+        stream::unfold(self, |rx| async move {
+            let i = rx.recv().await?;
+            Some((i, rx))
+        })
+    }
+}
+
+struct QueryStats(Arc<QueryChannel>);
+
+impl QueryStats {
+    fn print(&self) {
+        let state = self.0.state.lock().unwrap();
+        println!(
+            "Queue depth peaked at {}, {} queries were dropped",
+            state.max_depth, state.dropped
+        );
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
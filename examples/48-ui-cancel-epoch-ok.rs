@@ -0,0 +1,296 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 25 queries, buffered by 3");
+    cancel_queries_buffered(5, 3).await?;
+    Ok(())
+}
+
+async fn cancel_queries_buffered(
+    n: usize,
+    buf_factor: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = validity();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = Query> + 'a>(
+    stream: S,
+    valid_reader: &'a ValidityReader,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = Query> + 'a {
+    stream.filter(move |query| {
+        let is_valid = valid_reader.is_current(query.epoch);
+        println!("## filter({:?}) = {}", query, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<Query>,
+    n: usize,
+    valid_writer: ValidityWriter,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        let epoch = valid_writer.set(range.clone());
+        for j in range {
+            let query = Query { id: j, epoch };
+            println!("## unbounded_send({:?})", query);
+            tx.unbounded_send(query).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = Query>,
+    buf_factor: usize,
+    valid_reader: &ValidityReader,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(|query| get_data_cancellable(query, valid_reader))
+        .buffered(buf_factor)
+        .for_each(|result| async move {
+            let outcome = match result {
+                Ok(data) if valid_reader.is_current(data.epoch) => Outcome::Valid,
+                Ok(_) => Outcome::Expired,
+                Err(QueryError::Cancelled) => Outcome::Cancelled,
+                Err(QueryError::Failed) => Outcome::Failed,
+            };
+            counter_writer.record(outcome);
+            println!("## result = {:?} ({:?})", result, outcome);
+        })
+        .await;
+}
+
+#[derive(Clone, Copy)]
+struct Query {
+    id: usize,
+    epoch: usize,
+}
+
+impl std::fmt::Debug for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("q:{}@{}", self.id, self.epoch))
+    }
+}
+
+fn validity() -> (ValidityWriter, ValidityReader) {
+    let (tx, rx) = watch::channel(());
+    let epoch = Arc::new(AtomicUsize::new(0));
+    (
+        ValidityWriter {
+            tx,
+            epoch: epoch.clone(),
+        },
+        ValidityReader { rx, epoch },
+    )
+}
+
+struct ValidityWriter {
+    tx: watch::Sender<()>,
+    epoch: Arc<AtomicUsize>,
+}
+
+impl ValidityWriter {
+    fn set(&self, range: Range<usize>) -> usize {
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+        println!(
+            "[{}] ## range changed to {:?} (epoch {})",
+            START_TIME.elapsed().as_millis(),
+            range,
+            epoch
+        );
+        self.tx.send_replace(());
+        epoch
+    }
+}
+
+#[derive(Clone)]
+struct ValidityReader {
+    rx: watch::Receiver<()>,
+    epoch: Arc<AtomicUsize>,
+}
+
+impl ValidityReader {
+    fn is_current(&self, epoch: usize) -> bool {
+        self.epoch.load(Ordering::SeqCst) == epoch
+    }
+
+    async fn changed(&mut self) -> Option<()> {
+        self.rx.changed().await.ok()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data {
+    id: usize,
+    epoch: usize,
+}
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.id))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Cancelled,
+    Failed,
+}
+
+async fn get_data(query: Query) -> Result<Data, QueryError> {
+    let i = query.id;
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data {
+            id: i,
+            epoch: query.epoch,
+        })
+    }
+}
+
+async fn get_data_cancellable(
+    query: Query,
+    valid_reader: &ValidityReader,
+) -> Result<Data, QueryError> {
+    let mut valid_reader = valid_reader.clone();
+    let data = get_data(query);
+    tokio::pin!(data);
+    loop {
+        if !valid_reader.is_current(query.epoch) {
+            println!(
+                "[{}] ## get_data({}) cancelled",
+                START_TIME.elapsed().as_millis(),
+                query.id
+            );
+            return Err(QueryError::Cancelled);
+        }
+        tokio::select! {
+            result = &mut data => return result,
+            Some(()) = valid_reader.changed() => {}
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel queries for 2 panes and pinned rows over 10 steps, buffered by 3");
+    cancel_queries_buffered(10, 3).await?;
+    Ok(())
+}
+
+async fn cancel_queries_buffered(
+    n: usize,
+    buf_factor: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        let panes = vec![Pane::new("left", 100, 5, 10), Pane::new("right", 500, 5, 3)];
+        send_task_moving_panes(tx, n, 0..3, panes, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+struct Pane {
+    name: &'static str,
+    start: usize,
+    len: usize,
+    max_speed: usize,
+}
+
+impl Pane {
+    fn new(name: &'static str, start: usize, len: usize, max_speed: usize) -> Pane {
+        Pane {
+            name,
+            start,
+            len,
+            max_speed,
+        }
+    }
+
+    fn range(&self) -> Range<usize> {
+        self.start..self.start + self.len
+    }
+
+    fn scroll(&mut self) {
+        self.start += Uniform::from(0..=self.max_speed).sample(&mut rand::thread_rng());
+    }
+}
+
+async fn send_task_moving_panes(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    pinned: Range<usize>,
+    mut panes: Vec<Pane>,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let mut visible = RangeSet::new();
+    let mut pinned = Some(pinned);
+    for i in 0..n {
+        if i > 0 {
+            for pane in panes.iter_mut() {
+                pane.scroll();
+            }
+        }
+        for pane in panes.iter() {
+            println!("## pane {} shows {:?}", pane.name, pane.range());
+        }
+        valid_writer.replace(pinned.iter().cloned().chain(panes.iter().map(Pane::range)));
+
+        if i == n / 2 {
+            if let Some(rows) = pinned.take() {
+                println!("## unpin rows {:?}", rows);
+                valid_writer.remove(rows);
+                for pane in panes.iter() {
+                    valid_writer.add(pane.range());
+                }
+            }
+        }
+
+        let previous = visible;
+        visible = valid_writer.snapshot();
+        for j in visible.iter().filter(|j| !previous.contains(*j)) {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(get_data)
+        .buffered(buf_factor)
+        .for_each(|result| async move {
+            let outcome = match result {
+                Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+                Ok(_) => Outcome::Expired,
+                Err(QueryError::Failed) => Outcome::Failed,
+            };
+            counter_writer.record(outcome);
+            println!("## result = {:?} ({:?})", result, outcome);
+        })
+        .await;
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    ranges: Arc<RwLock<RangeSet>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(RangeSet::new()));
+        let reader = writer.clone();
+        (ValidRange { ranges: writer }, ValidRange { ranges: reader })
+    }
+
+    fn add(&self, range: Range<usize>) {
+        let mut ranges = self.ranges.write().unwrap();
+        ranges.add(range);
+        println!("## valid ranges = {:?}", *ranges);
+    }
+
+    fn remove(&self, range: Range<usize>) {
+        let mut ranges = self.ranges.write().unwrap();
+        ranges.remove(range);
+        println!("## valid ranges = {:?}", *ranges);
+    }
+
+    fn replace(&self, new_ranges: impl IntoIterator<Item = Range<usize>>) {
+        let mut ranges = self.ranges.write().unwrap();
+        ranges.replace(new_ranges);
+        println!("## valid ranges = {:?}", *ranges);
+    }
+
+    fn snapshot(&self) -> RangeSet {
+        self.ranges.read().unwrap().clone()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.ranges.read().unwrap().contains(x)
+    }
+}
+
+#[derive(Clone, Default)]
+struct RangeSet {
+    ranges: Vec<Range<usize>>,
+}
+
+impl RangeSet {
+    fn new() -> RangeSet {
+        RangeSet::default()
+    }
+
+    fn add(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let mut merged = range;
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        for r in self.ranges.drain(..) {
+            if r.end < merged.start || r.start > merged.end {
+                ranges.push(r);
+            } else {
+                merged = merged.start.min(r.start)..merged.end.max(r.end);
+            }
+        }
+        ranges.push(merged);
+        ranges.sort_by_key(|r| r.start);
+        self.ranges = ranges;
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        for r in self.ranges.drain(..) {
+            if r.end <= range.start || r.start >= range.end {
+                ranges.push(r);
+                continue;
+            }
+            if r.start < range.start {
+                ranges.push(r.start..range.start);
+            }
+            if r.end > range.end {
+                ranges.push(range.end..r.end);
+            }
+        }
+        self.ranges = ranges;
+    }
+
+    fn replace(&mut self, ranges: impl IntoIterator<Item = Range<usize>>) {
+        self.ranges.clear();
+        for range in ranges {
+            self.add(range);
+        }
+    }
+
+    fn contains(&self, x: usize) -> bool {
+        self.ranges
+            .binary_search_by(|r| {
+                if r.end <= x {
+                    std::cmp::Ordering::Less
+                } else if r.start > x {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ranges.iter().flat_map(|r| r.clone())
+    }
+}
+
+impl std::fmt::Debug for RangeSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.ranges.iter()).finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,395 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let models = [
+        ScrollModel::Steady { rows_per_frame: 2 },
+        ScrollModel::Fling {
+            velocity: 12.0,
+            friction: 0.8,
+        },
+        ScrollModel::Jump { max_position: 1000 },
+        ScrollModel::BackAndForth {
+            rows_per_frame: 3,
+            span: 15,
+        },
+    ];
+    for model in models.iter() {
+        for capacity in [0, 20].iter() {
+            println!(
+                "Cancel queries for {:?} over 20 frames, cache of {} items, buffered by 3",
+                model, capacity
+            );
+            cancel_queries_buffered(ScrollSimulator::new(*model, 5, 20, 42), 3, *capacity).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn cancel_queries_buffered(
+    simulator: ScrollSimulator,
+    buf_factor: usize,
+    capacity: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+    let cache = DataCache::new(capacity);
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_scrolling(tx, simulator, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            &valid_reader,
+            &cache,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ScrollModel {
+    Steady { rows_per_frame: usize },
+    Fling { velocity: f64, friction: f64 },
+    Jump { max_position: usize },
+    BackAndForth { rows_per_frame: usize, span: usize },
+}
+
+struct ScrollSimulator {
+    model: ScrollModel,
+    rng: StdRng,
+    viewport: usize,
+    frames: usize,
+    frame: usize,
+    position: usize,
+    velocity: f64,
+    forward: bool,
+}
+
+impl ScrollSimulator {
+    fn new(model: ScrollModel, viewport: usize, frames: usize, seed: u64) -> ScrollSimulator {
+        let velocity = match model {
+            ScrollModel::Fling { velocity, .. } => velocity,
+            _ => 0.0,
+        };
+        ScrollSimulator {
+            model,
+            rng: StdRng::seed_from_u64(seed),
+            viewport,
+            frames,
+            frame: 0,
+            position: 0,
+            velocity,
+            forward: true,
+        }
+    }
+}
+
+impl Iterator for ScrollSimulator {
+    type Item = (Range<usize>, Duration);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame == self.frames {
+            return None;
+        }
+        if self.frame > 0 {
+            match self.model {
+                ScrollModel::Steady { rows_per_frame } => self.position += rows_per_frame,
+                ScrollModel::Fling { friction, .. } => {
+                    self.position += self.velocity.round() as usize;
+                    self.velocity *= friction;
+                }
+                ScrollModel::Jump { max_position } => {
+                    self.position = self.rng.gen_range(0..max_position.max(1))
+                }
+                ScrollModel::BackAndForth {
+                    rows_per_frame,
+                    span,
+                } => {
+                    if self.forward {
+                        self.position += rows_per_frame;
+                        self.forward = self.position < span;
+                    } else {
+                        self.position = self.position.saturating_sub(rows_per_frame);
+                        self.forward = self.position == 0;
+                    }
+                }
+            }
+        }
+        self.frame += 1;
+
+        let millis = match self.model {
+            ScrollModel::Jump { .. } => self.rng.gen_range(5..25),
+            _ => self.rng.gen_range(0..10),
+        };
+        Some((
+            self.position..self.position + self.viewport,
+            Duration::from_millis(millis),
+        ))
+    }
+}
+
+async fn send_task_scrolling(
+    tx: UnboundedSender<usize>,
+    simulator: ScrollSimulator,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let mut previous = 0..0;
+    for (i, (range, duration)) in simulator.enumerate() {
+        println!("## viewport({}) = {:?}", i, range);
+        valid_writer.set(range.clone());
+        for j in range.clone().filter(|j| !previous.contains(j)) {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        previous = range;
+
+        println!("## sleep({}) for {} ms", i, duration.as_millis());
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    cache: &DataCache,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(|i| async move {
+        if let Some(data) = cache.get(i) {
+            println!("## cache hit for {}", i);
+            counter_writer.hit();
+            return Ok(data);
+        }
+        let result = get_data(i).await;
+        if let Ok(data) = result {
+            cache.insert(data);
+        }
+        result
+    })
+    .buffered(buf_factor)
+    .for_each(|result| async move {
+        let outcome = match result {
+            Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+            Ok(_) => Outcome::Expired,
+            Err(QueryError::Failed) => Outcome::Failed,
+        };
+        counter_writer.record(outcome);
+        println!("## result = {:?} ({:?})", result, outcome);
+    })
+    .await;
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+struct DataCache {
+    entries: Mutex<HashMap<usize, (Data, usize)>>,
+    tick: AtomicUsize,
+    capacity: usize,
+}
+
+impl DataCache {
+    fn new(capacity: usize) -> DataCache {
+        DataCache {
+            entries: Mutex::new(HashMap::new()),
+            tick: AtomicUsize::new(0),
+            capacity,
+        }
+    }
+
+    fn get(&self, i: usize) -> Option<Data> {
+        let tick = self.tick.fetch_add(1, Ordering::SeqCst);
+        let mut entries = self.entries.lock().unwrap();
+        let (data, last_used) = entries.get_mut(&i)?;
+        *last_used = tick;
+        Some(*data)
+    }
+
+    fn insert(&self, data: Data) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.tick.fetch_add(1, Ordering::SeqCst);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity && !entries.contains_key(&data.0) {
+            let (&lru, _) = entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .unwrap();
+            println!("## cache evicts {}", lru);
+            entries.remove(&lru);
+        }
+        entries.insert(data.0, (data, tick));
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    hits: AtomicUsize,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+        let hits = self.hits.load(Ordering::SeqCst);
+        println!(
+            "Made {} backend queries, {} were served from the cache",
+            valid + expired + failed - hits,
+            hits
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,474 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::future::{abortable, AbortHandle, Aborted};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let models = [
+        ScrollModel::Steady { rows_per_frame: 2 },
+        ScrollModel::Fling {
+            velocity: 12.0,
+            friction: 0.8,
+        },
+        ScrollModel::Jump { max_position: 1000 },
+        ScrollModel::BackAndForth {
+            rows_per_frame: 3,
+            span: 15,
+        },
+    ];
+    for model in models.iter() {
+        for prefetch_len in [0, 5].iter() {
+            println!(
+                "Cancel queries for {:?} over 20 frames, prefetching {} ids, buffered by 3",
+                model, prefetch_len
+            );
+            let simulator = ScrollSimulator::new(*model, 5, 20, 42);
+            cancel_queries_prefetching(simulator, 3, *prefetch_len).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn cancel_queries_prefetching(
+    simulator: ScrollSimulator,
+    buf_factor: usize,
+    prefetch_len: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_scrolling(tx, simulator, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_prefetching(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            prefetch_len,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ScrollModel {
+    Steady { rows_per_frame: usize },
+    Fling { velocity: f64, friction: f64 },
+    Jump { max_position: usize },
+    BackAndForth { rows_per_frame: usize, span: usize },
+}
+
+struct ScrollSimulator {
+    model: ScrollModel,
+    rng: StdRng,
+    viewport: usize,
+    frames: usize,
+    frame: usize,
+    position: usize,
+    velocity: f64,
+    forward: bool,
+}
+
+impl ScrollSimulator {
+    fn new(model: ScrollModel, viewport: usize, frames: usize, seed: u64) -> ScrollSimulator {
+        let velocity = match model {
+            ScrollModel::Fling { velocity, .. } => velocity,
+            _ => 0.0,
+        };
+        ScrollSimulator {
+            model,
+            rng: StdRng::seed_from_u64(seed),
+            viewport,
+            frames,
+            frame: 0,
+            position: 0,
+            velocity,
+            forward: true,
+        }
+    }
+}
+
+impl Iterator for ScrollSimulator {
+    type Item = (Range<usize>, Duration);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame == self.frames {
+            return None;
+        }
+        if self.frame > 0 {
+            match self.model {
+                ScrollModel::Steady { rows_per_frame } => self.position += rows_per_frame,
+                ScrollModel::Fling { friction, .. } => {
+                    self.position += self.velocity.round() as usize;
+                    self.velocity *= friction;
+                }
+                ScrollModel::Jump { max_position } => {
+                    self.position = self.rng.gen_range(0..max_position.max(1))
+                }
+                ScrollModel::BackAndForth {
+                    rows_per_frame,
+                    span,
+                } => {
+                    if self.forward {
+                        self.position += rows_per_frame;
+                        self.forward = self.position < span;
+                    } else {
+                        self.position = self.position.saturating_sub(rows_per_frame);
+                        self.forward = self.position == 0;
+                    }
+                }
+            }
+        }
+        self.frame += 1;
+
+        let millis = match self.model {
+            ScrollModel::Jump { .. } => self.rng.gen_range(5..25),
+            _ => self.rng.gen_range(0..10),
+        };
+        Some((
+            self.position..self.position + self.viewport,
+            Duration::from_millis(millis),
+        ))
+    }
+}
+
+async fn send_task_scrolling(
+    tx: UnboundedSender<usize>,
+    simulator: ScrollSimulator,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let mut previous = 0..0;
+    for (i, (range, duration)) in simulator.enumerate() {
+        println!("## viewport({}) = {:?}", i, range);
+        valid_writer.set(range.clone());
+        for j in range.clone().filter(|j| !previous.contains(j)) {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        previous = range;
+
+        println!("## sleep({}) for {} ms", i, duration.as_millis());
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+async fn receive_task_prefetching(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    prefetch_len: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    let mut rx = Box::pin(rx.fuse());
+    let mut visible = FuturesUnordered::new();
+    let mut prefetches = FuturesUnordered::new();
+    let mut prefetch_queue = VecDeque::new();
+    let mut prefetching: HashMap<usize, AbortHandle> = HashMap::new();
+    let mut wanted = HashSet::new();
+    let mut prefetched = HashMap::new();
+    let mut last_range = 0..0;
+    let mut direction = None;
+
+    loop {
+        tokio::select! {
+            biased;
+            Some(data) = visible.next() => observe(data, valid_reader, counter_writer),
+            Some(i) = rx.next(), if visible.len() + prefetches.len() < buf_factor => {
+                let range = valid_reader.get();
+                if range != last_range {
+                    let new_direction = if range.start >= last_range.start {
+                        Direction::Forward
+                    } else {
+                        Direction::Backward
+                    };
+                    if direction.is_some() && direction != Some(new_direction) {
+                        println!("## direction flipped, cancelling prefetches");
+                        for (j, handle) in prefetching.iter() {
+                            if !wanted.contains(j) {
+                                handle.abort();
+                            }
+                        }
+                        prefetch_queue.clear();
+                    }
+                    direction = Some(new_direction);
+
+                    let ahead = match new_direction {
+                        Direction::Forward => range.end..range.end + prefetch_len,
+                        Direction::Backward => {
+                            range.start.saturating_sub(prefetch_len)..range.start
+                        }
+                    };
+                    for j in ahead {
+                        if !prefetched.contains_key(&j)
+                            && !prefetching.contains_key(&j)
+                            && !prefetch_queue.contains(&j)
+                        {
+                            prefetch_queue.push_back(j);
+                        }
+                    }
+                    last_range = range;
+                }
+
+                prefetch_queue.retain(|j| *j != i);
+                match prefetched.remove(&i) {
+                    Some(data) => {
+                        println!("## prefetch hit for {}", i);
+                        counter_writer.prefetch_hit();
+                        observe(Ok(data), valid_reader, counter_writer);
+                    }
+                    None if prefetching.contains_key(&i) && !wanted.contains(&i) => {
+                        println!("## waiting for prefetch({})", i);
+                        wanted.insert(i);
+                    }
+                    None => visible.push(get_data(i)),
+                }
+            }
+            Some((i, result)) = prefetches.next() => {
+                prefetching.remove(&i);
+                match result {
+                    Ok(result) => {
+                        counter_writer.prefetched();
+                        if wanted.remove(&i) {
+                            println!("## prefetch hit for {}", i);
+                            counter_writer.prefetch_hit();
+                            observe(result, valid_reader, counter_writer);
+                        } else if let Ok(data) = result {
+                            prefetched.insert(i, data);
+                        }
+                    }
+                    Err(Aborted) => {
+                        println!("## prefetch({}) cancelled", i);
+                        counter_writer.prefetch_cancelled();
+                        if wanted.remove(&i) {
+                            visible.push(get_data(i));
+                        }
+                    }
+                }
+            }
+            () = future::ready(()), if visible.len() + prefetches.len() < buf_factor
+                && !prefetch_queue.is_empty() => {
+                let i = prefetch_queue.pop_front().unwrap();
+                println!("## prefetch({})", i);
+                let (future, handle) = abortable(get_data(i));
+                prefetching.insert(i, handle);
+                prefetches.push(async move { (i, future.await) });
+            }
+            else => break,
+        }
+
+        if rx.is_done() && visible.is_empty() && wanted.is_empty() {
+            break;
+        }
+    }
+}
+
+fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn get(&self) -> Range<usize> {
+        self.range.read().unwrap().clone()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    prefetched: AtomicUsize,
+    prefetch_hits: AtomicUsize,
+    prefetch_cancelled: AtomicUsize,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            prefetched: AtomicUsize::new(0),
+            prefetch_hits: AtomicUsize::new(0),
+            prefetch_cancelled: AtomicUsize::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn prefetched(&self) {
+        self.prefetched.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn prefetch_hit(&self) {
+        self.prefetch_hits.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn prefetch_cancelled(&self) {
+        self.prefetch_cancelled.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+        let prefetched = self.prefetched.load(Ordering::SeqCst);
+        let prefetch_hits = self.prefetch_hits.load(Ordering::SeqCst);
+        let prefetch_cancelled = self.prefetch_cancelled.load(Ordering::SeqCst);
+
+        println!(
+            "Prefetched {} ids ({} cancelled by a direction flip), {} were shown later: hit rate {}%",
+            prefetched,
+            prefetch_cancelled,
+            prefetch_hits,
+            100 * prefetch_hits / prefetched.max(1)
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,364 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Fuse, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 50 queries in FIFO order, buffered by 2");
+    cancel_queries_prioritized(5, 2, |_, _| 0).await?;
+    println!("Cancel 50 queries closest to the viewport center first, buffered by 2");
+    cancel_queries_prioritized(5, 2, distance_from_center).await?;
+    println!("Cancel 50 queries closest to the viewport bottom first, buffered by 2");
+    cancel_queries_prioritized(5, 2, |i, visible| {
+        (visible.end as isize - 1 - i as isize).unsigned_abs()
+    })
+    .await?;
+    Ok(())
+}
+
+fn distance_from_center(i: usize, visible: &Range<usize>) -> usize {
+    let center = (visible.start + visible.end) / 2;
+    (i as isize - center as isize).unsigned_abs()
+}
+
+async fn cancel_queries_prioritized(
+    n: usize,
+    buf_factor: usize,
+    priority: impl Fn(usize, &Range<usize>) -> usize + Send + Unpin + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        let rx = prioritize(rx, valid_reader.clone(), priority);
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+fn prioritize<S, F>(stream: S, valid_range: ValidRange, priority: F) -> Prioritize<S, F>
+where
+    S: Stream<Item = usize>,
+    F: Fn(usize, &Range<usize>) -> usize,
+{
+    Prioritize {
+        stream: Box::pin(stream.fuse()),
+        pending: Vec::new(),
+        valid_range,
+        priority,
+    }
+}
+
+struct Prioritize<S, F> {
+    stream: Pin<Box<Fuse<S>>>,
+    pending: Vec<usize>,
+    valid_range: ValidRange,
+    priority: F,
+}
+
+impl<S, F> Stream for Prioritize<S, F>
+where
+    S: Stream<Item = usize>,
+    F: Fn(usize, &Range<usize>) -> usize + Unpin,
+{
+    type Item = usize;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(i)) = this.stream.as_mut().poll_next(cx) {
+            this.pending.push(i);
+        }
+
+        let (visible, _) = this.valid_range.viewport();
+        let priority = &this.priority;
+        let next = this
+            .pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, i)| priority(**i, &visible))
+            .map(|(index, _)| index);
+        match next {
+            Some(index) => {
+                let i = this.pending.remove(index);
+                println!(
+                    "## schedule({}) among {} pending",
+                    i,
+                    this.pending.len() + 1
+                );
+                Poll::Ready(Some(i))
+            }
+            None if this.stream.is_done() => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 10;
+        valid_writer.set(range.clone(), 10 * i + 3..10 * i + 7);
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(10..20).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    let mut results = Box::pin(rx.map(get_data).buffered(buf_factor));
+    let mut filling = 0..0;
+    let mut missing = HashSet::new();
+    while let Some(result) = results.next().await {
+        let outcome = match result {
+            Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+            Ok(_) => Outcome::Expired,
+            Err(QueryError::Failed) => Outcome::Failed,
+        };
+        counter_writer.record(outcome);
+        println!("## result = {:?} ({:?})", result, outcome);
+
+        let (visible, since) = valid_reader.viewport();
+        if visible != filling {
+            counter_writer.viewport();
+            missing = visible.clone().collect();
+            filling = visible;
+        }
+        if let Ok(data) = result {
+            if missing.remove(&data.0) && missing.is_empty() {
+                println!(
+                    "## viewport {:?} filled after {} ms",
+                    filling,
+                    since.elapsed().as_millis()
+                );
+                counter_writer.filled(since.elapsed());
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    viewport: Arc<RwLock<Viewport>>,
+}
+
+struct Viewport {
+    range: Range<usize>,
+    visible: Range<usize>,
+    since: Instant,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(Viewport {
+            range: 0..0,
+            visible: 0..0,
+            since: Instant::now(),
+        }));
+        let reader = writer.clone();
+        (
+            ValidRange { viewport: writer },
+            ValidRange { viewport: reader },
+        )
+    }
+
+    fn set(&self, range: Range<usize>, visible: Range<usize>) {
+        *self.viewport.write().unwrap() = Viewport {
+            range,
+            visible,
+            since: Instant::now(),
+        };
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.viewport.read().unwrap().range.contains(&x)
+    }
+
+    fn viewport(&self) -> (Range<usize>, Instant) {
+        let viewport = self.viewport.read().unwrap();
+        (viewport.visible.clone(), viewport.since)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    viewports: AtomicUsize,
+    filled: AtomicUsize,
+    fill_micros: AtomicU64,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            viewports: AtomicUsize::new(0),
+            filled: AtomicUsize::new(0),
+            fill_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn viewport(&self) {
+        self.viewports.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn filled(&self, duration: Duration) {
+        self.filled.fetch_add(1, Ordering::SeqCst);
+        self.fill_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+        let viewports = self.viewports.load(Ordering::SeqCst);
+        let filled = self.filled.load(Ordering::SeqCst);
+        let fill_micros = self.fill_micros.load(Ordering::SeqCst);
+
+        println!(
+            "Filled {} of {} viewports, after {} ms on average",
+            filled,
+            viewports,
+            fill_micros / filled.max(1) as u64 / 1000
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,366 @@
+use futures::channel::mpsc::{unbounded, TryRecvError, UnboundedReceiver, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+const FRAME: Duration = Duration::from_millis(16);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Render 50 queries at 60 fps, buffered by 1");
+    cancel_queries_rendered(10, 1).await?;
+    println!("Render 50 queries at 60 fps, buffered by 3");
+    cancel_queries_rendered(10, 3).await?;
+    Ok(())
+}
+
+async fn cancel_queries_rendered(
+    n: usize,
+    buf_factor: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (render_tx, render_rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+    let stats = Arc::new(FrameStats::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let render_reader = valid_reader.clone();
+    let receive = spawn(async move {
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            render_tx,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let stats_writer = stats.clone();
+    let render = spawn(async move {
+        render_task(render_rx, render_reader, stats_writer).await;
+    });
+
+    let (send_res, receive_res, render_res) = join!(send, receive, render);
+    send_res?;
+    receive_res?;
+    render_res?;
+
+    counter.print();
+    stats.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..50).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    render_tx: UnboundedSender<Data>,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(get_data)
+        .buffered(buf_factor)
+        .for_each(|result| {
+            let outcome = match result {
+                Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+                Ok(_) => Outcome::Expired,
+                Err(QueryError::Failed) => Outcome::Failed,
+            };
+            counter_writer.record(outcome);
+            println!("## result = {:?} ({:?})", result, outcome);
+            if let Ok(data) = result {
+                render_tx.unbounded_send(data).unwrap();
+            }
+            future::ready(())
+        })
+        .await;
+}
+
+async fn render_task(
+    mut rx: UnboundedReceiver<Data>,
+    valid_reader: ValidRange,
+    stats_writer: Arc<FrameStats>,
+) {
+    let mut ticks = interval(FRAME);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut loaded = HashSet::new();
+    let mut viewport_since = None;
+    let mut complete = false;
+
+    loop {
+        ticks.tick().await;
+
+        let mut arrived = Vec::new();
+        let mut closed = false;
+        loop {
+            match rx.try_recv() {
+                Ok(data) => {
+                    loaded.insert(data.0);
+                    arrived.push(data);
+                }
+                Err(TryRecvError::Closed) => {
+                    closed = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        let (visible, since) = valid_reader.get();
+        if viewport_since != Some(since) && !visible.is_empty() {
+            stats_writer.viewport();
+            viewport_since = Some(since);
+            complete = false;
+        }
+
+        let empty = visible.clone().filter(|i| !loaded.contains(i)).count();
+        println!(
+            "[{}] ### frame {:?}: arrived = {:?}, {} empty slots",
+            START_TIME.elapsed().as_millis(),
+            visible,
+            arrived,
+            empty
+        );
+        stats_writer.frame(empty);
+        if empty == 0 && !complete && viewport_since.is_some() {
+            complete = true;
+            stats_writer.complete(since.elapsed());
+        }
+
+        if closed {
+            break;
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<(Range<usize>, Instant)>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new((0..0, Instant::now())));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = (range, Instant::now());
+    }
+
+    fn get(&self) -> (Range<usize>, Instant) {
+        self.range.read().unwrap().clone()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().0.contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+struct FrameStats {
+    frames: AtomicUsize,
+    janky: AtomicUsize,
+    empty_slots: AtomicUsize,
+    viewports: AtomicUsize,
+    complete: AtomicUsize,
+    complete_micros: AtomicU64,
+}
+
+impl FrameStats {
+    fn new() -> FrameStats {
+        FrameStats {
+            frames: AtomicUsize::new(0),
+            janky: AtomicUsize::new(0),
+            empty_slots: AtomicUsize::new(0),
+            viewports: AtomicUsize::new(0),
+            complete: AtomicUsize::new(0),
+            complete_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn frame(&self, empty: usize) {
+        self.frames.fetch_add(1, Ordering::SeqCst);
+        if empty > 0 {
+            self.janky.fetch_add(1, Ordering::SeqCst);
+            self.empty_slots.fetch_add(empty, Ordering::SeqCst);
+        }
+    }
+
+    fn viewport(&self) {
+        self.viewports.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn complete(&self, duration: Duration) {
+        self.complete.fetch_add(1, Ordering::SeqCst);
+        self.complete_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let frames = self.frames.load(Ordering::SeqCst);
+        let janky = self.janky.load(Ordering::SeqCst);
+        let empty_slots = self.empty_slots.load(Ordering::SeqCst);
+        let viewports = self.viewports.load(Ordering::SeqCst);
+        let complete = self.complete.load(Ordering::SeqCst);
+        let complete_micros = self.complete_micros.load(Ordering::SeqCst);
+
+        println!(
+            "Rendered {} frames, {} janky with {} empty slots in total",
+            frames, janky, empty_slots
+        );
+        println!(
+            "Completed {} of {} viewports, after {} ms on average",
+            complete,
+            viewports,
+            complete_micros / complete.max(1) as u64 / 1000
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
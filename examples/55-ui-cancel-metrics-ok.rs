@@ -0,0 +1,364 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 25 queries, buffered by 1");
+    cancel_queries_buffered(5, 1).await?;
+    println!("Cancel 25 queries, buffered by 3");
+    cancel_queries_buffered(5, 3).await?;
+    Ok(())
+}
+
+async fn cancel_queries_buffered(
+    n: usize,
+    buf_factor: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    println!("Metrics: {}", counter.export());
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = Query> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = Query> + 'a {
+    stream.filter(move |query| {
+        let is_valid = valid_range.is_valid(query.id);
+        println!("## filter({}) = {}", query.id, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+#[derive(Clone, Copy)]
+struct Query {
+    id: usize,
+    sent: Instant,
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<Query>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(Query {
+                id: j,
+                sent: Instant::now(),
+            })
+            .unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = Query>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(|query| async move {
+        let started = Instant::now();
+        let result = get_data(query.id).await;
+        (result, started - query.sent, started.elapsed())
+    })
+    .buffered(buf_factor)
+    .for_each(|(result, queue_time, service_time)| async move {
+        let outcome = match result {
+            Ok(data) if valid_reader.is_valid(data.0) => {
+                counter_writer.first_valid(valid_reader.since());
+                Outcome::Valid
+            }
+            Ok(_) => Outcome::Expired,
+            Err(QueryError::Failed) => Outcome::Failed,
+        };
+        counter_writer.completed(outcome, queue_time, service_time);
+        println!(
+            "## result = {:?} ({:?}) after {} ms in queue and {} ms in service",
+            result,
+            outcome,
+            queue_time.as_millis(),
+            service_time.as_millis()
+        );
+    })
+    .await;
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<(Range<usize>, Instant)>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new((0..0, Instant::now())));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = (range, Instant::now());
+    }
+
+    fn since(&self) -> Instant {
+        self.range.read().unwrap().1
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().0.contains(&x)
+    }
+}
+
+struct Histogram {
+    micros: Mutex<Vec<u64>>,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            micros: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        self.micros
+            .lock()
+            .unwrap()
+            .push(duration.as_micros() as u64);
+    }
+
+    fn percentiles(&self) -> [(&'static str, f64); 4] {
+        let mut micros = self.micros.lock().unwrap().clone();
+        micros.sort_unstable();
+        let at = |p: usize| match micros.len() {
+            0 => 0.0,
+            len => micros[(len - 1) * p / 100] as f64 / 1000.0,
+        };
+        [
+            ("p50", at(50)),
+            ("p90", at(90)),
+            ("p99", at(99)),
+            ("max", at(100)),
+        ]
+    }
+
+    fn print(&self, name: &str) {
+        let percentiles: Vec<String> = self
+            .percentiles()
+            .iter()
+            .map(|(p, ms)| format!("{} = {:.1} ms", p, ms))
+            .collect();
+        println!("{}: {}", name, percentiles.join(", "));
+    }
+
+    fn export(&self) -> String {
+        let percentiles: Vec<String> = self
+            .percentiles()
+            .iter()
+            .map(|(p, ms)| format!("\"{}\":{:.3}", p, ms))
+            .collect();
+        format!("{{{}}}", percentiles.join(","))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    wasted_micros: AtomicU64,
+    queue_time: Histogram,
+    service_time: Histogram,
+    first_valid_time: Histogram,
+    first_valid_since: Mutex<Option<Instant>>,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            wasted_micros: AtomicU64::new(0),
+            queue_time: Histogram::new(),
+            service_time: Histogram::new(),
+            first_valid_time: Histogram::new(),
+            first_valid_since: Mutex::new(None),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn completed(&self, outcome: Outcome, queue_time: Duration, service_time: Duration) {
+        self.record(outcome);
+        if let Outcome::Expired = outcome {
+            self.wasted_micros
+                .fetch_add(service_time.as_micros() as u64, Ordering::SeqCst);
+        }
+        self.queue_time.record(queue_time);
+        self.service_time.record(service_time);
+    }
+
+    fn first_valid(&self, since: Instant) {
+        let mut first_valid_since = self.first_valid_since.lock().unwrap();
+        if *first_valid_since != Some(since) {
+            *first_valid_since = Some(since);
+            self.first_valid_time.record(since.elapsed());
+        }
+    }
+
+    fn outcomes(&self) -> [usize; 5] {
+        [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst))
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = self.outcomes();
+        let wasted_micros = self.wasted_micros.load(Ordering::SeqCst);
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+        println!(
+            "Expired results wasted {} ms of backend time",
+            wasted_micros / 1000
+        );
+        self.queue_time.print("Queue time");
+        self.service_time.print("Service time");
+        self.first_valid_time
+            .print("Time from range change to first valid result");
+    }
+
+    fn export(&self) -> String {
+        let [filtered, cancelled, valid, expired, failed] = self.outcomes();
+        format!(
+            "{{\"sent\":{},\"filtered\":{},\"cancelled\":{},\"valid\":{},\"expired\":{},\"failed\":{},\"wasted_ms\":{:.3},\"queue_ms\":{},\"service_ms\":{},\"first_valid_ms\":{}}}",
+            self.sent.load(Ordering::SeqCst),
+            filtered,
+            cancelled,
+            valid,
+            expired,
+            failed,
+            self.wasted_micros.load(Ordering::SeqCst) as f64 / 1000.0,
+            self.queue_time.export(),
+            self.service_time.export(),
+            self.first_valid_time.export()
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,507 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::future::{abortable, AbortHandle, Aborted};
+use futures::stream::{self, Fuse, FuturesOrdered, Stream, StreamExt};
+use futures::task::{Context, Poll};
+use futures::{future, join, Future};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::task::{self, JoinSet};
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for strategy in [
+        Strategy::NoCancel,
+        Strategy::Filter,
+        Strategy::FilterUnordered,
+        Strategy::InFlight,
+        Strategy::SwitchMap,
+        Strategy::JoinSet,
+        Strategy::Select,
+    ]
+    .iter()
+    {
+        println!("Send 25 queries with {:?}, buffered by 3", strategy);
+        send_queries(5, 3, *strategy).await?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Strategy {
+    NoCancel,
+    Filter,
+    FilterUnordered,
+    InFlight,
+    SwitchMap,
+    JoinSet,
+    Select,
+}
+
+async fn send_queries(
+    n: usize,
+    buf_factor: usize,
+    strategy: Strategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        let valid_reader = &valid_reader;
+        let counter_writer = &counter_writer;
+        match strategy {
+            Strategy::NoCancel => {
+                let results = rx.flat_map(stream::iter).map(get_data).buffered(buf_factor);
+                observe(results, valid_reader, counter_writer).await
+            }
+            Strategy::Filter => {
+                let results = cancel(rx.flat_map(stream::iter), valid_reader, counter_writer)
+                    .map(get_data)
+                    .buffered(buf_factor);
+                observe(results, valid_reader, counter_writer).await
+            }
+            Strategy::FilterUnordered => {
+                let results = cancel(rx.flat_map(stream::iter), valid_reader, counter_writer)
+                    .map(get_data)
+                    .buffer_unordered(buf_factor);
+                observe(results, valid_reader, counter_writer).await
+            }
+            Strategy::InFlight => {
+                let results = cancel(rx.flat_map(stream::iter), valid_reader, counter_writer)
+                    .map(|i| get_data_cancellable(i, valid_reader))
+                    .buffered(buf_factor);
+                observe(results, valid_reader, counter_writer).await
+            }
+            Strategy::SwitchMap => {
+                let results = switch_map(rx, |range| {
+                    let mut not_started = NotStarted {
+                        remaining: range.len(),
+                        counter_writer,
+                    };
+                    stream::iter(range)
+                        .inspect(move |_| not_started.start())
+                        .map(move |i| get_data_switchable(i, counter_writer))
+                        .buffered(buf_factor)
+                        .map(|(result, guard)| {
+                            std::mem::forget(guard);
+                            result
+                        })
+                });
+                observe(results, valid_reader, counter_writer).await
+            }
+            Strategy::JoinSet => {
+                let rx = cancel(rx.flat_map(stream::iter), valid_reader, counter_writer);
+                receive_task_join_set(rx, buf_factor, valid_reader, counter_writer).await
+            }
+            Strategy::Select => {
+                let rx = cancel(rx.flat_map(stream::iter), valid_reader, counter_writer);
+                receive_task_select(rx, buf_factor, valid_reader, counter_writer).await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<Range<usize>>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        println!("## unbounded_send({:?})", range);
+        for _ in range.clone() {
+            counter_writer.sent();
+        }
+        tx.unbounded_send(range).unwrap();
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_join_set(
+    rx: impl Stream<Item = usize>,
+    max_tasks: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let mut rx = Box::pin(rx.fuse());
+    let mut tasks = JoinSet::new();
+    let mut handles: HashMap<usize, task::AbortHandle> = HashMap::new();
+    let mut changes = valid_reader.changes();
+
+    loop {
+        tokio::select! {
+            Ok(()) = changes.changed(), if !handles.is_empty() => {
+                handles.retain(|i, handle| {
+                    let is_valid = valid_reader.is_valid(*i);
+                    if !is_valid {
+                        println!("## abort({})", i);
+                        handle.abort();
+                    }
+                    is_valid
+                });
+            }
+            Some(result) = tasks.join_next() => match result {
+                Ok((i, result)) => {
+                    handles.remove(&i);
+                    report(result, valid_reader, counter_writer);
+                }
+                Err(e) if e.is_cancelled() => report(Err(QueryError::Cancelled), valid_reader, counter_writer),
+                Err(e) => panic!("get_data task failed: {}", e),
+            },
+            Some(i) = rx.next(), if tasks.len() < max_tasks => {
+                let handle = tasks.spawn(async move { (i, get_data(i).await) });
+                handles.insert(i, handle);
+            }
+            else => break,
+        }
+    }
+}
+
+async fn receive_task_select(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let mut rx = Box::pin(rx.fuse());
+    let mut pending = FuturesOrdered::new();
+    let mut handles: VecDeque<(usize, AbortHandle)> = VecDeque::new();
+    let mut changes = valid_reader.changes();
+
+    loop {
+        tokio::select! {
+            biased;
+            Ok(()) = changes.changed(), if !handles.is_empty() => {
+                for (i, handle) in handles.iter() {
+                    if !valid_reader.is_valid(*i) {
+                        println!("## prune({})", i);
+                        handle.abort();
+                    }
+                }
+            }
+            Some(result) = pending.next() => {
+                handles.pop_front();
+                let result = match result {
+                    Ok(result) => result,
+                    Err(Aborted) => Err(QueryError::Cancelled),
+                };
+                report(result, valid_reader, counter_writer);
+            }
+            Some(i) = rx.next(), if pending.len() < buf_factor => {
+                let (future, handle) = abortable(get_data(i));
+                pending.push_back(future);
+                handles.push_back((i, handle));
+            }
+            else => break,
+        }
+    }
+}
+
+async fn observe(
+    results: impl Stream<Item = Result<Data, QueryError>>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    results
+        .for_each(|result| {
+            report(result, valid_reader, counter_writer);
+            future::ready(())
+        })
+        .await;
+}
+
+fn report(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Cancelled) => Outcome::Cancelled,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+fn switch_map<S, U, F>(stream: S, f: F) -> SwitchMap<S, U, F>
+where
+    S: Stream,
+    U: Stream,
+    F: FnMut(S::Item) -> U,
+{
+    SwitchMap {
+        stream: Box::pin(stream.fuse()),
+        inner: None,
+        f,
+    }
+}
+
+struct SwitchMap<S, U, F> {
+    stream: Pin<Box<Fuse<S>>>,
+    inner: Option<Pin<Box<U>>>,
+    f: F,
+}
+
+impl<S, U, F> Stream for SwitchMap<S, U, F>
+where
+    S: Stream,
+    U: Stream,
+    F: FnMut(S::Item) -> U + Unpin,
+{
+    type Item = U::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(item)) = this.stream.as_mut().poll_next(cx) {
+            if this.inner.is_some() {
+                println!("## switch_map: dropping previous batch");
+            }
+            this.inner = Some(Box::pin((this.f)(item)));
+        }
+
+        if let Some(inner) = this.inner.as_mut() {
+            match inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => return Poll::Ready(Some(item)),
+                Poll::Ready(None) => this.inner = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        if this.stream.is_done() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct NotStarted<'a> {
+    remaining: usize,
+    counter_writer: &'a ValidCounter,
+}
+
+impl NotStarted<'_> {
+    fn start(&mut self) {
+        self.remaining -= 1;
+    }
+}
+
+impl Drop for NotStarted<'_> {
+    fn drop(&mut self) {
+        for _ in 0..self.remaining {
+            self.counter_writer.record(Outcome::Filtered);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+    changed: Arc<watch::Sender<()>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let (changed, _) = watch::channel(());
+        let writer = ValidRange {
+            range: Arc::new(RwLock::new(0..0)),
+            changed: Arc::new(changed),
+        };
+        let reader = writer.clone();
+        (writer, reader)
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+        self.changed.send_replace(());
+    }
+
+    fn changes(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] =
+            [0, 1, 2, 3, 4].map(|i| self.outcomes[i].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Cancelled,
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
+
+async fn get_data_cancellable(i: usize, valid_reader: &ValidRange) -> Result<Data, QueryError> {
+    let data = get_data(i);
+    tokio::pin!(data);
+    let mut changes = valid_reader.changes();
+    loop {
+        if !valid_reader.is_valid(i) {
+            println!(
+                "[{}] ## get_data({}) cancelled",
+                START_TIME.elapsed().as_millis(),
+                i
+            );
+            return Err(QueryError::Cancelled);
+        }
+        tokio::select! {
+            result = &mut data => return result,
+            _ = changes.changed() => {}
+        }
+    }
+}
+
+fn get_data_switchable(
+    i: usize,
+    counter_writer: &ValidCounter,
+) -> impl Future<Output = (Result<Data, QueryError>, CancelGuard<'_>)> {
+    let guard = CancelGuard { i, counter_writer };
+    async move { (get_data(i).await, guard) }
+}
+
+struct CancelGuard<'a> {
+    i: usize,
+    counter_writer: &'a ValidCounter,
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        println!(
+            "[{}] ## get_data({}) cancelled",
+            START_TIME.elapsed().as_millis(),
+            self.i
+        );
+        self.counter_writer.record(Outcome::Cancelled);
+    }
+}
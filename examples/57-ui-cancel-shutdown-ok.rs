@@ -0,0 +1,421 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{FuturesOrdered, Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::signal;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Send 50 queries, buffered by 3, until completion or Ctrl-C");
+    run_queries(10, 3, Scenario::Complete).await?;
+    println!("Send 50 queries, buffered by 3, shutting down after 30 ms");
+    run_queries(10, 3, Scenario::Shutdown(Duration::from_millis(30))).await?;
+    println!("Send 50 queries, buffered by 3, with a receiver failing after 8 results");
+    run_queries(10, 3, Scenario::ReceiverFailure(8)).await?;
+    Ok(())
+}
+
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Copy)]
+enum Scenario {
+    Complete,
+    Shutdown(Duration),
+    ReceiverFailure(usize),
+}
+
+enum StopReason {
+    Completed,
+    Shutdown,
+    ReceiverGone,
+    Failed(String),
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Completed => f.write_str("completed"),
+            StopReason::Shutdown => f.write_str("shut down"),
+            StopReason::ReceiverGone => f.write_str("receiver gone"),
+            StopReason::Failed(e) => f.write_fmt(format_args!("failed: {}", e)),
+        }
+    }
+}
+
+struct SendSummary {
+    sent: usize,
+    reason: StopReason,
+}
+
+impl SendSummary {
+    fn print(&self) {
+        println!("Sender sent {} queries ({})", self.sent, self.reason);
+    }
+}
+
+struct ReceiveSummary {
+    rendered: usize,
+    drained: usize,
+    abandoned: usize,
+    reason: StopReason,
+}
+
+impl ReceiveSummary {
+    fn print(&self) {
+        println!(
+            "Receiver rendered {} results, {} drained after shutdown, {} abandoned ({})",
+            self.rendered, self.drained, self.abandoned, self.reason
+        );
+    }
+}
+
+async fn run_queries(
+    n: usize,
+    buf_factor: usize,
+    scenario: Scenario,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let counter = Arc::new(ValidCounter::new());
+
+    let signal = spawn(async move {
+        let timeout = match scenario {
+            Scenario::Shutdown(timeout) => Some(timeout),
+            _ => None,
+        };
+        let ctrl_c = async {
+            match signal::ctrl_c().await {
+                Ok(()) => println!("## received Ctrl-C"),
+                Err(e) => {
+                    println!("## cannot listen for Ctrl-C: {}", e);
+                    future::pending().await
+                }
+            }
+        };
+        tokio::select! {
+            () = ctrl_c => {}
+            _ = sleep(timeout.unwrap_or_default()), if timeout.is_some() => {
+                println!("[{}] ## shutdown requested", START_TIME.elapsed().as_millis())
+            }
+        }
+        shutdown_tx.send_replace(true);
+    });
+
+    let shutdown = shutdown_rx.clone();
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, shutdown, &counter_writer).await
+    });
+
+    let fail_after = match scenario {
+        Scenario::ReceiverFailure(fail_after) => Some(fail_after),
+        _ => None,
+    };
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        let rx = rx.inspect(|_| counter_writer.received());
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            &valid_reader,
+            shutdown_rx,
+            fail_after,
+            &counter_writer,
+        )
+        .await
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    signal.abort();
+    send_res?.print();
+    receive_res?.print();
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    mut shutdown: watch::Receiver<bool>,
+    counter_writer: &ValidCounter,
+) -> SendSummary {
+    let mut sent = 0;
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            if tx.unbounded_send(j).is_err() {
+                println!("## receiver is gone, stopping the sender");
+                return SendSummary {
+                    sent,
+                    reason: StopReason::ReceiverGone,
+                };
+            }
+            sent += 1;
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        tokio::select! {
+            _ = sleep(duration) => println!("## sleep({}) completed", i),
+            Ok(()) = shutdown.changed() => {
+                println!("## shutdown, stopping the sender");
+                return SendSummary {
+                    sent,
+                    reason: StopReason::Shutdown,
+                };
+            }
+        }
+    }
+    SendSummary {
+        sent,
+        reason: StopReason::Completed,
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    mut shutdown: watch::Receiver<bool>,
+    fail_after: Option<usize>,
+    counter_writer: &ValidCounter,
+) -> ReceiveSummary {
+    let mut rx = Box::pin(rx.fuse());
+    let mut in_flight = FuturesOrdered::new();
+    let mut summary = ReceiveSummary {
+        rendered: 0,
+        drained: 0,
+        abandoned: 0,
+        reason: StopReason::Completed,
+    };
+
+    while !(rx.is_done() && in_flight.is_empty()) {
+        tokio::select! {
+            biased;
+            Ok(()) = shutdown.changed() => {
+                println!("## shutdown, draining {} queries in flight", in_flight.len());
+                summary.reason = StopReason::Shutdown;
+                break;
+            }
+            Some(result) = in_flight.next() => {
+                if let Err(e) = observe(result, valid_reader, &mut summary, fail_after, counter_writer) {
+                    println!("## {}, stopping the receiver", e);
+                    summary.reason = StopReason::Failed(e);
+                    abandon(&mut summary, in_flight.len(), counter_writer);
+                    return summary;
+                }
+            }
+            next = rx.next(), if !rx.is_done() && in_flight.len() < buf_factor => match next {
+                Some(i) => in_flight.push_back(get_data(i)),
+                None => println!("## channel closed, {} queries in flight", in_flight.len()),
+            },
+            else => break,
+        }
+    }
+    if !matches!(summary.reason, StopReason::Shutdown) {
+        return summary;
+    }
+
+    let deadline = sleep(DRAIN_TIMEOUT);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            Some(result) = in_flight.next() => {
+                summary.drained += 1;
+                if observe(result, valid_reader, &mut summary, None, counter_writer).is_err() {
+                    break;
+                }
+            }
+            _ = &mut deadline => {
+                println!("## drain timed out with {} queries in flight", in_flight.len());
+                break;
+            }
+            else => break,
+        }
+    }
+    abandon(&mut summary, in_flight.len(), counter_writer);
+    summary
+}
+
+fn abandon(summary: &mut ReceiveSummary, in_flight: usize, counter_writer: &ValidCounter) {
+    summary.abandoned = in_flight;
+    for _ in 0..in_flight {
+        counter_writer.record(Outcome::Cancelled);
+    }
+}
+
+fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    summary: &mut ReceiveSummary,
+    fail_after: Option<usize>,
+    counter_writer: &ValidCounter,
+) -> Result<(), String> {
+    if fail_after == Some(summary.rendered) {
+        counter_writer.record(Outcome::Failed);
+        return Err(format!("failed to render {:?}", result));
+    }
+
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    summary.rendered += 1;
+    println!("## result = {:?} ({:?})", result, outcome);
+    Ok(())
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn received(&self) {
+        self.received.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let received = self.received.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+        let filtered = filtered + sent - received;
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
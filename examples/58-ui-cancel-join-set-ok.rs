@@ -0,0 +1,322 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 250 queries, buffered by 3");
+    cancel_queries(50, 3, Strategy::Buffered).await?;
+    println!("Cancel 250 queries, one task each with at most 3 running");
+    cancel_queries(50, 3, Strategy::JoinSet).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Strategy {
+    Buffered,
+    JoinSet,
+}
+
+async fn cancel_queries(
+    n: usize,
+    buf_factor: usize,
+    strategy: Strategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+    let start = Instant::now();
+    let start_cpu = cpu_time();
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        let rx = cancel(rx, &valid_reader, &counter_writer);
+        match strategy {
+            Strategy::Buffered => {
+                receive_task_buffered(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+            Strategy::JoinSet => {
+                receive_task_join_set(rx, buf_factor, &valid_reader, &counter_writer).await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print(
+        start.elapsed(),
+        start_cpu.zip(cpu_time()).map(|(a, b)| b - a),
+    );
+    Ok(())
+}
+
+fn cpu_time() -> Option<Duration> {
+    let mut nanos = 0;
+    for task in std::fs::read_dir("/proc/self/task").ok()? {
+        let schedstat = std::fs::read_to_string(task.ok()?.path().join("schedstat")).ok()?;
+        nanos += schedstat.split_whitespace().next()?.parse::<u64>().ok()?;
+    }
+    Some(Duration::from_nanos(nanos))
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(get_data)
+        .buffered(buf_factor)
+        .for_each(|data| async move { observe(data, valid_reader, counter_writer) })
+        .await;
+}
+
+async fn receive_task_join_set(
+    rx: impl Stream<Item = usize>,
+    max_tasks: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    let mut rx = Box::pin(rx.fuse());
+    let semaphore = Arc::new(Semaphore::new(max_tasks));
+    let mut permit = None;
+    let mut changes = valid_reader.changes();
+    let mut tasks = JoinSet::new();
+    let mut handles: HashMap<usize, AbortHandle> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            biased;
+            Ok(()) = changes.changed(), if !handles.is_empty() => {
+                handles.retain(|i, handle| {
+                    let is_valid = valid_reader.is_valid(*i);
+                    if !is_valid {
+                        println!("## abort({})", i);
+                        handle.abort();
+                    }
+                    is_valid
+                });
+            }
+            Some(result) = tasks.join_next() => match result {
+                Ok((i, result)) => {
+                    handles.remove(&i);
+                    observe(result, valid_reader, counter_writer);
+                }
+                Err(e) if e.is_cancelled() => counter_writer.record(Outcome::Cancelled),
+                Err(e) => panic!("get_data task failed: {}", e),
+            },
+            acquired = semaphore.clone().acquire_owned(), if permit.is_none() && !rx.is_done() => {
+                permit = Some(acquired.unwrap());
+            }
+            Some(i) = rx.next(), if permit.is_some() && !rx.is_done() => {
+                let permit = permit.take().unwrap();
+                let handle = tasks.spawn(async move {
+                    let _permit = permit;
+                    (i, get_data(i).await)
+                });
+                handles.insert(i, handle);
+            }
+            else => break,
+        }
+    }
+}
+
+fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+    changed: Arc<watch::Sender<()>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let (changed, _) = watch::channel(());
+        let writer = ValidRange {
+            range: Arc::new(RwLock::new(0..0)),
+            changed: Arc::new(changed),
+        };
+        let reader = writer.clone();
+        (writer, reader)
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+        self.changed.send_replace(());
+    }
+
+    fn changes(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self, elapsed: Duration, cpu: Option<Duration>) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] =
+            [0, 1, 2, 3, 4].map(|i| self.outcomes[i].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+        println!(
+            "Got {} results in {} ms ({:.0} results/s), using {} of CPU time",
+            valid + expired + failed,
+            elapsed.as_millis(),
+            (valid + expired + failed) as f64 / elapsed.as_secs_f64(),
+            match cpu {
+                Some(cpu) => format!("{:.1} ms", cpu.as_secs_f64() * 1000.0),
+                None => "an unknown amount".to_string(),
+            }
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,398 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join, Future, FutureExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::{oneshot, Notify};
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for policy in [Policy::Fifo, Policy::RoundRobin, Policy::Weighted].iter() {
+        println!(
+            "Share a backend of 2 workers between 3 clients, {:?}",
+            policy
+        );
+        share_backend(2, *policy).await?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Policy {
+    Fifo,
+    RoundRobin,
+    Weighted,
+}
+
+#[derive(Clone, Copy)]
+struct Client {
+    name: &'static str,
+    frames: usize,
+    frame_millis: u64,
+    buf_factor: usize,
+    weight: usize,
+}
+
+const CLIENTS: [Client; 3] = [
+    Client {
+        name: "fast",
+        frames: 40,
+        frame_millis: 2,
+        buf_factor: 12,
+        weight: 1,
+    },
+    Client {
+        name: "slow-1",
+        frames: 5,
+        frame_millis: 20,
+        buf_factor: 2,
+        weight: 3,
+    },
+    Client {
+        name: "slow-2",
+        frames: 5,
+        frame_millis: 20,
+        buf_factor: 2,
+        weight: 3,
+    },
+];
+
+async fn share_backend(workers: usize, policy: Policy) -> Result<(), Box<dyn std::error::Error>> {
+    let weights = CLIENTS.iter().map(|client| client.weight).collect();
+    let backend = Arc::new(Backend::new(policy, weights));
+    let workers: Vec<_> = (0..workers)
+        .map(|_| {
+            let backend = backend.clone();
+            spawn(async move { backend.work().await })
+        })
+        .collect();
+
+    let clients = CLIENTS.iter().enumerate().map(|(index, client)| {
+        let backend = backend.clone();
+        spawn(async move { run_client(index, *client, backend).await })
+    });
+    let counters = future::try_join_all(clients).await?;
+
+    for worker in workers {
+        worker.abort();
+    }
+    for (client, counter) in CLIENTS.iter().zip(counters) {
+        print!("Client {}: ", client.name);
+        counter.print();
+    }
+    Ok(())
+}
+
+async fn run_client(index: usize, client: Client, backend: Arc<Backend>) -> Arc<ValidCounter> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let send = send_task_tracking_validity(tx, client, valid_writer, &counter);
+    let receive = receive_task_buffered(
+        cancel(rx, &valid_reader, &counter),
+        client.buf_factor,
+        |i| backend.query(index, i),
+        &valid_reader,
+        &counter,
+    );
+    join!(send, receive);
+
+    counter
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    client: Client,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..client.frames {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## {}: unbounded_send({})", client.name, j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        sleep(Duration::from_millis(client.frame_millis)).await;
+    }
+}
+
+async fn receive_task_buffered<F: Future<Output = Result<Data, QueryError>>>(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    query: impl Fn(usize) -> F,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    rx.map(|i| {
+        let start = Instant::now();
+        query(i).map(move |result| (result, start.elapsed()))
+    })
+    .buffered(buf_factor)
+    .for_each(|(result, latency)| {
+        let outcome = match result {
+            Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+            Ok(_) => Outcome::Expired,
+            Err(QueryError::Failed) => Outcome::Failed,
+        };
+        counter_writer.record(outcome);
+        counter_writer.latency(latency);
+        future::ready(())
+    })
+    .await;
+}
+
+struct Request {
+    seq: usize,
+    i: usize,
+    reply: oneshot::Sender<Result<Data, QueryError>>,
+}
+
+struct Backend {
+    scheduler: Mutex<Scheduler>,
+    available: Notify,
+}
+
+impl Backend {
+    fn new(policy: Policy, weights: Vec<usize>) -> Backend {
+        Backend {
+            scheduler: Mutex::new(Scheduler::new(policy, weights)),
+            available: Notify::new(),
+        }
+    }
+
+    fn query(&self, client: usize, i: usize) -> impl Future<Output = Result<Data, QueryError>> {
+        let (reply, response) = oneshot::channel();
+        self.scheduler.lock().unwrap().push(client, i, reply);
+        self.available.notify_one();
+        async move { response.await.unwrap() }
+    }
+
+    async fn work(&self) {
+        loop {
+            let request = self.scheduler.lock().unwrap().pop();
+            match request {
+                Some(request) => {
+                    let result = get_data(request.i).await;
+                    let _ = request.reply.send(result);
+                }
+                None => self.available.notified().await,
+            }
+        }
+    }
+}
+
+struct Scheduler {
+    policy: Policy,
+    queues: Vec<VecDeque<Request>>,
+    weights: Vec<usize>,
+    seq: usize,
+    cursor: usize,
+    turns: usize,
+}
+
+impl Scheduler {
+    fn new(policy: Policy, weights: Vec<usize>) -> Scheduler {
+        Scheduler {
+            policy,
+            queues: weights.iter().map(|_| VecDeque::new()).collect(),
+            weights,
+            seq: 0,
+            cursor: 0,
+            turns: 0,
+        }
+    }
+
+    fn push(&mut self, client: usize, i: usize, reply: oneshot::Sender<Result<Data, QueryError>>) {
+        self.queues[client].push_back(Request {
+            seq: self.seq,
+            i,
+            reply,
+        });
+        self.seq += 1;
+    }
+
+    fn pop(&mut self) -> Option<Request> {
+        match self.policy {
+            Policy::Fifo => {
+                let (client, _) = self
+                    .queues
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(client, queue)| Some((client, queue.front()?.seq)))
+                    .min_by_key(|(_, seq)| *seq)?;
+                self.queues[client].pop_front()
+            }
+            Policy::RoundRobin | Policy::Weighted => {
+                for _ in 0..=self.queues.len() {
+                    let weight = match self.policy {
+                        Policy::Weighted => self.weights[self.cursor],
+                        _ => 1,
+                    };
+                    if self.turns < weight {
+                        if let Some(request) = self.queues[self.cursor].pop_front() {
+                            self.turns += 1;
+                            return Some(request);
+                        }
+                    }
+                    self.cursor = (self.cursor + 1) % self.queues.len();
+                    self.turns = 0;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    latency_micros: AtomicU64,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            latency_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn latency(&self, latency: Duration) {
+        self.latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        let latency_micros = self.latency_micros.load(Ordering::SeqCst);
+        println!(
+            "{} ms average latency over {} queries",
+            latency_micros / (valid + expired + failed).max(1) as u64 / 1000,
+            valid + expired + failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
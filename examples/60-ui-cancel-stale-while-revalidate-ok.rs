@@ -0,0 +1,495 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::future::{abortable, AbortHandle, Aborted};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let models = [
+        ScrollModel::Steady { rows_per_frame: 2 },
+        ScrollModel::Fling {
+            velocity: 12.0,
+            friction: 0.8,
+        },
+        ScrollModel::Jump { max_position: 20 },
+        ScrollModel::BackAndForth {
+            rows_per_frame: 3,
+            span: 15,
+        },
+    ];
+    for model in models.iter() {
+        for ttl in [10, 50].iter() {
+            println!(
+                "Cancel queries for {:?} over 30 frames, stale after {} ms, buffered by 3",
+                model, ttl
+            );
+            let simulator = ScrollSimulator::new(*model, 5, 30, 42);
+            cancel_queries_revalidating(simulator, 3, Duration::from_millis(*ttl)).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn cancel_queries_revalidating(
+    simulator: ScrollSimulator,
+    buf_factor: usize,
+    ttl: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_scrolling(tx, simulator, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_revalidating(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            ttl,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ScrollModel {
+    Steady { rows_per_frame: usize },
+    Fling { velocity: f64, friction: f64 },
+    Jump { max_position: usize },
+    BackAndForth { rows_per_frame: usize, span: usize },
+}
+
+struct ScrollSimulator {
+    model: ScrollModel,
+    rng: StdRng,
+    viewport: usize,
+    frames: usize,
+    frame: usize,
+    position: usize,
+    velocity: f64,
+    forward: bool,
+}
+
+impl ScrollSimulator {
+    fn new(model: ScrollModel, viewport: usize, frames: usize, seed: u64) -> ScrollSimulator {
+        let velocity = match model {
+            ScrollModel::Fling { velocity, .. } => velocity,
+            _ => 0.0,
+        };
+        ScrollSimulator {
+            model,
+            rng: StdRng::seed_from_u64(seed),
+            viewport,
+            frames,
+            frame: 0,
+            position: 0,
+            velocity,
+            forward: true,
+        }
+    }
+}
+
+impl Iterator for ScrollSimulator {
+    type Item = (Range<usize>, Duration);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame == self.frames {
+            return None;
+        }
+        if self.frame > 0 {
+            match self.model {
+                ScrollModel::Steady { rows_per_frame } => self.position += rows_per_frame,
+                ScrollModel::Fling { friction, .. } => {
+                    self.position += self.velocity.round() as usize;
+                    self.velocity *= friction;
+                }
+                ScrollModel::Jump { max_position } => {
+                    self.position = self.rng.gen_range(0..max_position.max(1))
+                }
+                ScrollModel::BackAndForth {
+                    rows_per_frame,
+                    span,
+                } => {
+                    if self.forward {
+                        self.position += rows_per_frame;
+                        self.forward = self.position < span;
+                    } else {
+                        self.position = self.position.saturating_sub(rows_per_frame);
+                        self.forward = self.position == 0;
+                    }
+                }
+            }
+        }
+        self.frame += 1;
+
+        let millis = match self.model {
+            ScrollModel::Jump { .. } => self.rng.gen_range(5..25),
+            _ => self.rng.gen_range(0..10),
+        };
+        Some((
+            self.position..self.position + self.viewport,
+            Duration::from_millis(millis),
+        ))
+    }
+}
+
+async fn send_task_scrolling(
+    tx: UnboundedSender<usize>,
+    simulator: ScrollSimulator,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let mut previous = 0..0;
+    for (i, (range, duration)) in simulator.enumerate() {
+        println!("## viewport({}) = {:?}", i, range);
+        valid_writer.set(range.clone());
+        for j in range.clone().filter(|j| !previous.contains(j)) {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        previous = range;
+
+        println!("## sleep({}) for {} ms", i, duration.as_millis());
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_revalidating(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    ttl: Duration,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    let mut rx = Box::pin(rx.fuse());
+    let mut visible = FuturesUnordered::new();
+    let mut visible_handles: HashMap<usize, AbortHandle> = HashMap::new();
+    let mut refreshes = FuturesUnordered::new();
+    let mut refresh_handles: HashMap<usize, AbortHandle> = HashMap::new();
+    let mut refresh_queue = VecDeque::new();
+    let mut cache: HashMap<usize, Data> = HashMap::new();
+    let mut changes = valid_reader.changes();
+
+    loop {
+        while visible.len() + refreshes.len() < buf_factor {
+            let i = match refresh_queue.pop_front() {
+                Some(i) => i,
+                None => break,
+            };
+            let (future, handle) = abortable(get_data(i));
+            refresh_handles.insert(i, handle);
+            refreshes.push(async move { (i, future.await) });
+        }
+
+        tokio::select! {
+            biased;
+            Ok(()) = changes.changed(), if !visible_handles.is_empty() || !refresh_handles.is_empty() || !refresh_queue.is_empty() => {
+                visible_handles.retain(|i, handle| {
+                    let is_valid = valid_reader.is_valid(*i);
+                    if !is_valid {
+                        println!("## abort({})", i);
+                        handle.abort();
+                    }
+                    is_valid
+                });
+                refresh_handles.retain(|i, handle| {
+                    let is_valid = valid_reader.is_valid(*i);
+                    if !is_valid {
+                        handle.abort();
+                    }
+                    is_valid
+                });
+                refresh_queue.retain(|i| {
+                    let is_valid = valid_reader.is_valid(*i);
+                    if !is_valid {
+                        println!("## refresh({}) dropped", i);
+                        counter_writer.refresh_cancelled();
+                    }
+                    is_valid
+                });
+            }
+            Some((i, result)) = visible.next() => {
+                visible_handles.remove(&i);
+                match result {
+                    Ok(result) => {
+                        if let Ok(data) = result {
+                            cache.insert(i, data);
+                        }
+                        observe(result, valid_reader, counter_writer);
+                    }
+                    Err(Aborted) => counter_writer.record(Outcome::Cancelled),
+                }
+            }
+            Some(i) = rx.next(), if visible.len() + refreshes.len() < buf_factor => match cache.get(&i) {
+                Some(data) if data.fetched.elapsed() < ttl => {
+                    println!("## render fresh {:?}", data);
+                    counter_writer.record(Outcome::Valid);
+                    counter_writer.fresh();
+                }
+                Some(data) => {
+                    println!(
+                        "## render stale {:?} ({} ms old), revalidating",
+                        data,
+                        data.fetched.elapsed().as_millis()
+                    );
+                    counter_writer.record(Outcome::Valid);
+                    counter_writer.stale();
+                    if !refresh_handles.contains_key(&i) && !refresh_queue.contains(&i) {
+                        refresh_queue.push_back(i);
+                    }
+                }
+                None => {
+                    let (future, handle) = abortable(get_data(i));
+                    visible_handles.insert(i, handle);
+                    visible.push(async move { (i, future.await) });
+                }
+            },
+            Some((i, result)) = refreshes.next() => {
+                refresh_handles.remove(&i);
+                match result {
+                    Ok(Ok(data)) => {
+                        cache.insert(i, data);
+                        let is_valid = valid_reader.is_valid(i);
+                        counter_writer.refreshed(is_valid);
+                        if is_valid {
+                            println!("## render refreshed {:?}", data);
+                        } else {
+                            println!("## refreshed {:?} (no longer visible)", data);
+                        }
+                    }
+                    Ok(Err(QueryError::Failed)) => {
+                        println!("## refresh({}) failed, keeping the stale value", i);
+                    }
+                    Err(Aborted) => {
+                        println!("## refresh({}) cancelled", i);
+                        counter_writer.refresh_cancelled();
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+}
+
+fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.id) => Outcome::Valid,
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+    changed: Arc<watch::Sender<()>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let (changed, _) = watch::channel(());
+        let writer = ValidRange {
+            range: Arc::new(RwLock::new(0..0)),
+            changed: Arc::new(changed),
+        };
+        let reader = writer.clone();
+        (writer, reader)
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+        self.changed.send_replace(());
+    }
+
+    fn changes(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    fresh: AtomicUsize,
+    stale: AtomicUsize,
+    refreshed: AtomicUsize,
+    refresh_rendered: AtomicUsize,
+    refresh_cancelled: AtomicUsize,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            fresh: AtomicUsize::new(0),
+            stale: AtomicUsize::new(0),
+            refreshed: AtomicUsize::new(0),
+            refresh_rendered: AtomicUsize::new(0),
+            refresh_cancelled: AtomicUsize::new(0),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn fresh(&self) {
+        self.fresh.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn stale(&self) {
+        self.stale.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn refreshed(&self, is_rendered: bool) {
+        self.refreshed.fetch_add(1, Ordering::SeqCst);
+        if is_rendered {
+            self.refresh_rendered.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn refresh_cancelled(&self) {
+        self.refresh_cancelled.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] =
+            [0, 1, 2, 3, 4].map(|i| self.outcomes[i].load(Ordering::SeqCst));
+        let fresh = self.fresh.load(Ordering::SeqCst);
+        let stale = self.stale.load(Ordering::SeqCst);
+        let refreshed = self.refreshed.load(Ordering::SeqCst);
+        let refresh_rendered = self.refresh_rendered.load(Ordering::SeqCst);
+        let refresh_cancelled = self.refresh_cancelled.load(Ordering::SeqCst);
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        println!(
+            "Rendered {} fresh and {} stale cached values, {} refreshed in the background ({} rendered), {} refreshes cancelled as the id scrolled away",
+            fresh, stale, refreshed, refresh_rendered, refresh_cancelled
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+static VERSION: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy)]
+struct Data {
+    id: usize,
+    version: usize,
+    fetched: Instant,
+}
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}@v{}", self.id, self.version))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data {
+            id: i,
+            version: VERSION.fetch_add(1, Ordering::SeqCst),
+            fetched: Instant::now(),
+        })
+    }
+}
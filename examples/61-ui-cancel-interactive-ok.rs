@@ -0,0 +1,458 @@
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Print, Stylize};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::tty::IsTty;
+use crossterm::{execute, queue};
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::{self, stdout, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::time::{interval, sleep};
+
+const VIEWPORT: usize = 10;
+const MARGIN: usize = 5;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let strategy = match std::env::args().nth(1).as_deref() {
+        Some("no-cancel") => Strategy::NoCancel,
+        None | Some("buffered") => Strategy::Buffered,
+        Some("buffer-unordered") => Strategy::BufferUnordered,
+        Some(other) => {
+            return Err(format!(
+                "unknown strategy {:?}, expected no-cancel, buffered or buffer-unordered",
+                other
+            )
+            .into())
+        }
+    };
+    if !stdout().is_tty() {
+        println!("This example needs an interactive terminal");
+        return Ok(());
+    }
+
+    let counter = run_interactive(strategy, 3).await?;
+    println!("Strategy {:?}, buffered by 3", strategy);
+    counter.print();
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Strategy {
+    NoCancel,
+    Buffered,
+    BufferUnordered,
+}
+
+async fn run_interactive(
+    strategy: Strategy,
+    buf_factor: usize,
+) -> Result<Arc<ValidCounter>, Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let rows = Arc::new(Rows::new());
+    let counter = Arc::new(ValidCounter::new());
+
+    let terminal = Terminal::enter()?;
+
+    let (render_reader, render_rows, render_counter) =
+        (valid_reader.clone(), rows.clone(), counter.clone());
+    let render = spawn(async move {
+        render_task(strategy, &render_reader, &render_rows, &render_counter).await
+    });
+
+    let (receive_rows, receive_counter) = (rows.clone(), counter.clone());
+    let receive = spawn(async move {
+        receive_task(
+            strategy,
+            rx,
+            buf_factor,
+            &valid_reader,
+            &receive_rows,
+            &receive_counter,
+        )
+        .await
+    });
+
+    let result = keyboard_task(tx, valid_writer, &rows, &counter).await;
+    render.abort();
+    receive.abort();
+    let _ = receive.await;
+    drop(terminal);
+
+    result?;
+    Ok(counter)
+}
+
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+async fn keyboard_task(
+    tx: UnboundedSender<usize>,
+    valid_writer: ValidRange,
+    rows: &Rows,
+    counter_writer: &ValidCounter,
+) -> io::Result<()> {
+    let mut events = EventStream::new();
+    let mut position = 0;
+    let mut previous = 0..0;
+
+    loop {
+        let range = position..position + VIEWPORT;
+        if range != previous {
+            valid_writer.set(range.clone());
+            for j in range.clone().filter(|j| !previous.contains(j)) {
+                if rows.request(j) {
+                    if tx.unbounded_send(j).is_err() {
+                        return Ok(());
+                    }
+                    counter_writer.sent();
+                }
+            }
+            previous = range;
+        }
+
+        let event = match events.next().await {
+            Some(event) => event?,
+            None => return Ok(()),
+        };
+        if let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event
+        {
+            position = match code {
+                KeyCode::Up => position.saturating_sub(1),
+                KeyCode::Down => position + 1,
+                KeyCode::PageUp => position.saturating_sub(VIEWPORT),
+                KeyCode::PageDown => position + VIEWPORT,
+                KeyCode::Home => 0,
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                _ => position,
+            };
+        }
+    }
+}
+
+async fn receive_task(
+    strategy: Strategy,
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    rows: &Rows,
+    counter_writer: &ValidCounter,
+) {
+    let rx = rx.inspect(|_| counter_writer.received());
+    let fetch = |i| {
+        rows.set(i, RowState::Fetching);
+        counter_writer.started();
+        get_data(i)
+    };
+    let observe = |(i, result): (usize, Result<Data, QueryError>)| {
+        let (outcome, state) = match result {
+            Ok(data) if valid_reader.is_valid(i) => (Outcome::Valid, RowState::Loaded(data)),
+            Ok(data) => (Outcome::Expired, RowState::Expired(data)),
+            Err(QueryError::Failed) => (Outcome::Failed, RowState::Failed),
+        };
+        counter_writer.record(outcome);
+        rows.set(i, state);
+        future::ready(())
+    };
+
+    match strategy {
+        Strategy::NoCancel => rx.map(fetch).buffered(buf_factor).for_each(observe).await,
+        Strategy::Buffered => {
+            cancel(rx, valid_reader, rows, counter_writer)
+                .map(fetch)
+                .buffered(buf_factor)
+                .for_each(observe)
+                .await
+        }
+        Strategy::BufferUnordered => {
+            cancel(rx, valid_reader, rows, counter_writer)
+                .map(fetch)
+                .buffer_unordered(buf_factor)
+                .for_each(observe)
+                .await
+        }
+    }
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    rows: &'a Rows,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        if !is_valid {
+            rows.set(*i, RowState::Cancelled);
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn render_task(
+    strategy: Strategy,
+    valid_reader: &ValidRange,
+    rows: &Rows,
+    counter: &ValidCounter,
+) -> io::Result<()> {
+    let mut ticks = interval(Duration::from_millis(50));
+    loop {
+        ticks.tick().await;
+        draw(strategy, valid_reader, rows, counter)?;
+    }
+}
+
+fn draw(
+    strategy: Strategy,
+    valid_reader: &ValidRange,
+    rows: &Rows,
+    counter: &ValidCounter,
+) -> io::Result<()> {
+    let mut stdout = stdout();
+    queue!(
+        stdout,
+        Clear(ClearType::All),
+        MoveTo(0, 0),
+        Print(format!(
+            "Strategy {:?} - Up/Down/PageUp/PageDown/Home to scroll, q to quit",
+            strategy
+        ))
+    )?;
+
+    let range = valid_reader.get();
+    let shown = range.start.saturating_sub(MARGIN)..range.end + MARGIN;
+    for (line, i) in shown.enumerate() {
+        let marker = if range.contains(&i) { ">" } else { " " };
+        let state = match rows.get(i) {
+            None => "".to_string().dark_grey(),
+            Some(RowState::Queued) => "queued".to_string().yellow(),
+            Some(RowState::Fetching) => "fetching".to_string().yellow().bold(),
+            Some(RowState::Loaded(data)) => format!("loaded {:?}", data).green(),
+            Some(RowState::Expired(data)) => format!("expired {:?}", data).red(),
+            Some(RowState::Cancelled) => "cancelled".to_string().dark_grey(),
+            Some(RowState::Failed) => "failed".to_string().red().bold(),
+        };
+        queue!(
+            stdout,
+            MoveTo(0, line as u16 + 2),
+            Print(format!("{} {:>5} ", marker, i)),
+            Print(state)
+        )?;
+    }
+
+    queue!(
+        stdout,
+        MoveTo(0, (VIEWPORT + 2 * MARGIN) as u16 + 3),
+        Print(counter.summary())
+    )?;
+    stdout.flush()
+}
+
+#[derive(Clone, Copy)]
+enum RowState {
+    Queued,
+    Fetching,
+    Loaded(Data),
+    Expired(Data),
+    Cancelled,
+    Failed,
+}
+
+struct Rows {
+    states: Mutex<HashMap<usize, RowState>>,
+}
+
+impl Rows {
+    fn new() -> Rows {
+        Rows {
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, i: usize) -> Option<RowState> {
+        self.states.lock().unwrap().get(&i).copied()
+    }
+
+    fn set(&self, i: usize, state: RowState) {
+        self.states.lock().unwrap().insert(i, state);
+    }
+
+    fn request(&self, i: usize) -> bool {
+        let mut states = self.states.lock().unwrap();
+        match states.get(&i).copied() {
+            None | Some(RowState::Cancelled) | Some(RowState::Failed) => {
+                states.insert(i, RowState::Queued);
+                true
+            }
+            Some(RowState::Expired(data)) => {
+                states.insert(i, RowState::Loaded(data));
+                false
+            }
+            Some(_) => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn get(&self) -> Range<usize> {
+        self.range.read().unwrap().clone()
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    started: AtomicUsize,
+    outcomes: [AtomicUsize; 4],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+            started: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn received(&self) {
+        self.received.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn started(&self) {
+        self.started.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn counts(&self) -> [usize; 6] {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let received = self.received.load(Ordering::SeqCst);
+        let started = self.started.load(Ordering::SeqCst);
+        let [filtered, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+        let in_flight = started - valid - expired - failed;
+        [
+            sent,
+            filtered + sent - received,
+            in_flight,
+            valid,
+            expired,
+            failed,
+        ]
+    }
+
+    fn summary(&self) -> String {
+        let [sent, filtered, in_flight, valid, expired, failed] = self.counts();
+        format!(
+            "Sent {} queries: {} filtered, {} in flight, {} valid, {} expired, {} failed",
+            sent, filtered, in_flight, valid, expired, failed
+        )
+    }
+
+    fn print(&self) {
+        let [sent, filtered, cancelled, valid, expired, failed] = self.counts();
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> (usize, Result<Data, QueryError>) {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(100..600).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    sleep(Duration::from_millis(millis)).await;
+    if fails {
+        (i, Err(QueryError::Failed))
+    } else {
+        (i, Ok(Data(i)))
+    }
+}
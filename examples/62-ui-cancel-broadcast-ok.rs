@@ -0,0 +1,433 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for policy in [LagPolicy::Drop, LagPolicy::Block, LagPolicy::Disconnect].iter() {
+        println!(
+            "Cancel 50 queries, buffered by 3, fanned out to 3 consumers with capacity 4 ({:?} lagging consumers)",
+            policy
+        );
+        cancel_queries_fanned_out(10, 3, *policy, 4).await?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum LagPolicy {
+    Drop,
+    Block,
+    Disconnect,
+}
+
+#[derive(Clone, Copy)]
+struct Consumer {
+    name: &'static str,
+    delay: Duration,
+}
+
+const CONSUMERS: [Consumer; 3] = [
+    Consumer {
+        name: "renderer",
+        delay: Duration::from_millis(1),
+    },
+    Consumer {
+        name: "logger",
+        delay: Duration::from_millis(0),
+    },
+    Consumer {
+        name: "metrics",
+        delay: Duration::from_millis(8),
+    },
+];
+
+async fn cancel_queries_fanned_out(
+    n: usize,
+    buf_factor: usize,
+    policy: LagPolicy,
+    capacity: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = ValidRange::new();
+    let counter = Arc::new(ValidCounter::new());
+    let stats: Vec<Arc<ConsumerStats>> = CONSUMERS
+        .iter()
+        .map(|_| Arc::new(ConsumerStats::new()))
+        .collect();
+    let (outputs, inputs) = fan_out(policy, capacity, stats.clone());
+    let start = Instant::now();
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let consumers = CONSUMERS
+        .iter()
+        .zip(inputs)
+        .zip(stats.iter().cloned())
+        .map(|((consumer, input), stats)| spawn(consume_task(*consumer, input, stats)));
+    let consumers = future::try_join_all(consumers);
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        receive_task_buffered(
+            cancel(rx, &valid_reader, &counter_writer),
+            buf_factor,
+            outputs,
+            &valid_reader,
+            &counter_writer,
+        )
+        .await;
+    });
+
+    let (send_res, receive_res, consumers_res) = join!(send, receive, consumers);
+    send_res?;
+    receive_res?;
+    consumers_res?;
+
+    counter.print();
+    for (consumer, stats) in CONSUMERS.iter().zip(stats) {
+        stats.print(consumer.name);
+    }
+    println!("Pipeline completed in {} ms", start.elapsed().as_millis());
+    Ok(())
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        let millis = Uniform::from(0..10).sample(&mut rand::thread_rng());
+        println!("## sleep({}) for {} ms", i, millis);
+
+        let duration = Duration::from_millis(millis);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    mut outputs: Outputs,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    let mut results = Box::pin(rx.map(get_data).buffered(buf_factor));
+    while let Some(result) = results.next().await {
+        let outcome = match result {
+            Ok(data) if valid_reader.is_valid(data.0) => Outcome::Valid,
+            Ok(_) => Outcome::Expired,
+            Err(QueryError::Failed) => Outcome::Failed,
+        };
+        counter_writer.record(outcome);
+        println!("## result = {:?} ({:?})", result, outcome);
+        if let Ok(data) = result {
+            outputs.send(data).await;
+        }
+    }
+}
+
+async fn consume_task(consumer: Consumer, mut input: Input, stats: Arc<ConsumerStats>) {
+    while let Some(data) = input.recv(&stats).await {
+        sleep(consumer.delay).await;
+        println!(
+            "[{}] ## {} got {:?}",
+            START_TIME.elapsed().as_millis(),
+            consumer.name,
+            data
+        );
+        stats.received();
+    }
+}
+
+fn fan_out(
+    policy: LagPolicy,
+    capacity: usize,
+    stats: Vec<Arc<ConsumerStats>>,
+) -> (Outputs, Vec<Input>) {
+    match policy {
+        LagPolicy::Drop => {
+            let (tx, _) = broadcast::channel(capacity);
+            let inputs = stats
+                .iter()
+                .map(|_| Input::Broadcast(tx.subscribe()))
+                .collect();
+            (Outputs::Broadcast(tx), inputs)
+        }
+        LagPolicy::Block | LagPolicy::Disconnect => {
+            let (senders, inputs) = stats
+                .into_iter()
+                .map(|stats| {
+                    let (tx, rx) = mpsc::channel(capacity);
+                    ((Some(tx), stats), Input::Channel(rx))
+                })
+                .unzip();
+            (Outputs::Channels(policy, senders), inputs)
+        }
+    }
+}
+
+enum Outputs {
+    Broadcast(broadcast::Sender<Data>),
+    Channels(
+        LagPolicy,
+        Vec<(Option<mpsc::Sender<Data>>, Arc<ConsumerStats>)>,
+    ),
+}
+
+impl Outputs {
+    async fn send(&mut self, data: Data) {
+        match self {
+            Outputs::Broadcast(tx) => {
+                let _ = tx.send(data);
+            }
+            Outputs::Channels(policy, senders) => {
+                for (sender, stats) in senders.iter_mut() {
+                    let tx = match sender {
+                        Some(tx) => tx,
+                        None => {
+                            stats.dropped(1);
+                            continue;
+                        }
+                    };
+                    match policy {
+                        LagPolicy::Block => {
+                            if tx.send(data).await.is_err() {
+                                *sender = None;
+                            }
+                        }
+                        _ => match tx.try_send(data) {
+                            Ok(()) => {}
+                            Err(TrySendError::Full(_)) => {
+                                println!("## consumer is lagging, disconnecting it");
+                                stats.disconnect();
+                                stats.dropped(1);
+                                *sender = None;
+                            }
+                            Err(TrySendError::Closed(_)) => *sender = None,
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum Input {
+    Broadcast(broadcast::Receiver<Data>),
+    Channel(mpsc::Receiver<Data>),
+}
+
+impl Input {
+    async fn recv(&mut self, stats: &ConsumerStats) -> Option<Data> {
+        match self {
+            Input::Broadcast(rx) => loop {
+                match rx.recv().await {
+                    Ok(data) => return Some(data),
+                    Err(RecvError::Lagged(skipped)) => {
+                        println!("## consumer lagged, {} results dropped", skipped);
+                        stats.dropped(skipped as usize);
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+            Input::Channel(rx) => rx.recv().await,
+        }
+    }
+}
+
+struct ConsumerStats {
+    received: AtomicUsize,
+    dropped: AtomicUsize,
+    disconnected: AtomicBool,
+}
+
+impl ConsumerStats {
+    fn new() -> ConsumerStats {
+        ConsumerStats {
+            received: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            disconnected: AtomicBool::new(false),
+        }
+    }
+
+    fn received(&self) {
+        self.received.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn dropped(&self, count: usize) {
+        self.dropped.fetch_add(count, Ordering::SeqCst);
+    }
+
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+    }
+
+    fn print(&self, name: &str) {
+        let received = self.received.load(Ordering::SeqCst);
+        let dropped = self.dropped.load(Ordering::SeqCst);
+        let disconnected = self.disconnected.load(Ordering::SeqCst);
+
+        println!(
+            "Consumer {} received {} results, {} dropped{}",
+            name,
+            received,
+            dropped,
+            if disconnected { ", disconnected" } else { "" }
+        );
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: Arc<RwLock<Range<usize>>>,
+}
+
+impl ValidRange {
+    fn new() -> (ValidRange, ValidRange) {
+        let writer = Arc::new(RwLock::new(0..0));
+        let reader = writer.clone();
+        (ValidRange { range: writer }, ValidRange { range: reader })
+    }
+
+    fn set(&self, range: Range<usize>) {
+        *self.range.write().unwrap() = range;
+    }
+
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.read().unwrap().contains(&x)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] = [
+            Outcome::Filtered,
+            Outcome::Cancelled,
+            Outcome::Valid,
+            Outcome::Expired,
+            Outcome::Failed,
+        ]
+        .map(|outcome| self.outcomes[outcome as usize].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let (millis, fails) = {
+        let mut rng = rand::thread_rng();
+        (Uniform::from(0..10).sample(&mut rng), rng.gen_bool(0.1))
+    };
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}
@@ -0,0 +1,326 @@
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::future::{abortable, AbortHandle, Aborted};
+use futures::stream::{FuturesOrdered, Stream, StreamExt};
+use futures::{future, join};
+use lazy_static::lazy_static;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+
+lazy_static! {
+    static ref START_TIME: Instant = Instant::now();
+}
+
+const STEP_MILLIS: u64 = 50;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Cancel 25 queries, buffered by 3, with the combinators of example 36");
+    let combinator = cancel_queries(5, 3, Strategy::Combinator).await?;
+    println!("Cancel 25 queries, buffered by 3, with a select loop");
+    let select = cancel_queries(5, 3, Strategy::Select).await?;
+
+    let select_results = select.valid_results();
+    assert!(combinator
+        .valid_results()
+        .iter()
+        .all(|i| select_results.contains(i)));
+    assert!(select.cancelled() > combinator.cancelled());
+    println!("The select loop kept every valid result of the combinators and cancelled more work");
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Strategy {
+    Combinator,
+    Select,
+}
+
+async fn cancel_queries(
+    n: usize,
+    buf_factor: usize,
+    strategy: Strategy,
+) -> Result<Arc<ValidCounter>, Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    let (valid_writer, valid_reader) = valid_range();
+    let counter = Arc::new(ValidCounter::new());
+
+    let counter_writer = counter.clone();
+    let send = spawn(async move {
+        send_task_tracking_validity(tx, n, valid_writer, &counter_writer).await;
+    });
+
+    let counter_writer = counter.clone();
+    let receive = spawn(async move {
+        match strategy {
+            Strategy::Combinator => {
+                receive_task_buffered(
+                    cancel(rx, &valid_reader, &counter_writer),
+                    buf_factor,
+                    &valid_reader,
+                    &counter_writer,
+                )
+                .await
+            }
+            Strategy::Select => {
+                receive_task_select(rx, buf_factor, valid_reader, &counter_writer).await
+            }
+        }
+    });
+
+    let (send_res, receive_res) = join!(send, receive);
+    send_res?;
+    receive_res?;
+
+    counter.print();
+    Ok(counter)
+}
+
+fn cancel<'a, S: Stream<Item = usize> + 'a>(
+    stream: S,
+    valid_range: &'a ValidRange,
+    counter_writer: &'a ValidCounter,
+) -> impl Stream<Item = usize> + 'a {
+    stream.filter(move |i| {
+        let is_valid = valid_range.is_valid(*i);
+        println!("## filter({}) = {}", i, is_valid);
+        if !is_valid {
+            counter_writer.record(Outcome::Filtered);
+        }
+        future::ready(is_valid)
+    })
+}
+
+async fn send_task_tracking_validity(
+    tx: UnboundedSender<usize>,
+    n: usize,
+    valid_writer: ValidWriter,
+    counter_writer: &ValidCounter,
+) {
+    for i in 0..n {
+        let range = 10 * i..10 * i + 5;
+        valid_writer.set(range.clone());
+        for j in range {
+            println!("## unbounded_send({})", j);
+            tx.unbounded_send(j).unwrap();
+            counter_writer.sent();
+        }
+        println!("## sleep({}) for {} ms", i, STEP_MILLIS);
+
+        let duration = Duration::from_millis(STEP_MILLIS);
+        sleep(duration).await;
+        println!("## sleep({}) completed", i);
+    }
+}
+
+async fn receive_task_buffered(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    valid_reader: &ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    rx.map(get_data)
+        .buffered(buf_factor)
+        .for_each(|result| async move { observe(result, valid_reader, counter_writer) })
+        .await;
+}
+
+async fn receive_task_select(
+    rx: impl Stream<Item = usize>,
+    buf_factor: usize,
+    mut valid_reader: ValidRange,
+    counter_writer: &Arc<ValidCounter>,
+) {
+    let mut rx = Box::pin(rx.fuse());
+    let mut pending = FuturesOrdered::new();
+    let mut handles: VecDeque<(usize, AbortHandle)> = VecDeque::new();
+
+    loop {
+        tokio::select! {
+            biased;
+            Ok(()) = valid_reader.changed() => {
+                for (i, handle) in handles.iter() {
+                    if !valid_reader.is_valid(*i) {
+                        println!("## prune({})", i);
+                        handle.abort();
+                    }
+                }
+            }
+            Some(result) = pending.next() => {
+                handles.pop_front();
+                match result {
+                    Ok(result) => observe(result, &valid_reader, counter_writer),
+                    Err(Aborted) => counter_writer.record(Outcome::Cancelled),
+                }
+            }
+            Some(i) = rx.next(), if pending.len() < buf_factor => {
+                let is_valid = valid_reader.is_valid(i);
+                println!("## filter({}) = {}", i, is_valid);
+                if is_valid {
+                    let (future, handle) = abortable(get_data(i));
+                    pending.push_back(future);
+                    handles.push_back((i, handle));
+                } else {
+                    counter_writer.record(Outcome::Filtered);
+                }
+            }
+            else => break,
+        }
+    }
+}
+
+fn observe(
+    result: Result<Data, QueryError>,
+    valid_reader: &ValidRange,
+    counter_writer: &ValidCounter,
+) {
+    let outcome = match result {
+        Ok(data) if valid_reader.is_valid(data.0) => {
+            counter_writer.valid(data);
+            Outcome::Valid
+        }
+        Ok(_) => Outcome::Expired,
+        Err(QueryError::Failed) => Outcome::Failed,
+    };
+    counter_writer.record(outcome);
+    println!("## result = {:?} ({:?})", result, outcome);
+}
+
+fn valid_range() -> (ValidWriter, ValidRange) {
+    let (tx, rx) = watch::channel(0..0);
+    (ValidWriter { range: tx }, ValidRange { range: rx })
+}
+
+struct ValidWriter {
+    range: watch::Sender<Range<usize>>,
+}
+
+impl ValidWriter {
+    fn set(&self, range: Range<usize>) {
+        self.range.send_replace(range);
+    }
+}
+
+#[derive(Clone)]
+struct ValidRange {
+    range: watch::Receiver<Range<usize>>,
+}
+
+impl ValidRange {
+    fn is_valid(&self, x: usize) -> bool {
+        self.range.borrow().contains(&x)
+    }
+
+    async fn changed(&mut self) -> Result<(), watch::error::RecvError> {
+        self.range.changed().await
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Filtered,
+    Cancelled,
+    Valid,
+    Expired,
+    Failed,
+}
+
+struct ValidCounter {
+    sent: AtomicUsize,
+    outcomes: [AtomicUsize; 5],
+    valid: Mutex<Vec<usize>>,
+}
+
+impl ValidCounter {
+    fn new() -> ValidCounter {
+        ValidCounter {
+            sent: AtomicUsize::new(0),
+            outcomes: Default::default(),
+            valid: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record(&self, outcome: Outcome) {
+        self.outcomes[outcome as usize].fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn valid(&self, data: Data) {
+        self.valid.lock().unwrap().push(data.0);
+    }
+
+    fn valid_results(&self) -> Vec<usize> {
+        self.valid.lock().unwrap().clone()
+    }
+
+    fn cancelled(&self) -> usize {
+        self.outcomes[Outcome::Cancelled as usize].load(Ordering::SeqCst)
+    }
+
+    fn print(&self) {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let [filtered, cancelled, valid, expired, failed] =
+            [0, 1, 2, 3, 4].map(|i| self.outcomes[i].load(Ordering::SeqCst));
+
+        println!(
+            "Sent {} queries: {} filtered, {} cancelled in flight, {} valid, {} expired, {} failed",
+            sent, filtered, cancelled, valid, expired, failed
+        );
+        println!("Valid results: {:?}", self.valid.lock().unwrap());
+        assert_eq!(filtered + cancelled + valid + expired + failed, sent);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Data(usize);
+
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("d:{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Failed,
+}
+
+async fn get_data(i: usize) -> Result<Data, QueryError> {
+    let mut rng = StdRng::seed_from_u64(i as u64);
+    let millis = if rng.gen_bool(0.25) {
+        STEP_MILLIS + 10
+    } else {
+        rng.gen_range(0..5)
+    };
+    let fails = rng.gen_bool(0.1);
+    println!(
+        "[{}] ## get_data({}) will {} in {} ms",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "fail" } else { "complete" },
+        millis
+    );
+
+    sleep(Duration::from_millis(millis)).await;
+    println!(
+        "[{}] ## get_data({}) {}",
+        START_TIME.elapsed().as_millis(),
+        i,
+        if fails { "failed" } else { "completed" }
+    );
+    if fails {
+        Err(QueryError::Failed)
+    } else {
+        Ok(Data(i))
+    }
+}